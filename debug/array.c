@@ -0,0 +1,18 @@
+const int N = 3;
+int g[2][3] = {{1, 2}, {3}};
+
+int sum(int a[][3], int n) {
+  int s = 0, i = 0;
+  while (i < n * N) {
+    s = s + a[i / N][i % N];
+    i = i + 1;
+  }
+  return s;
+}
+
+int main() {
+  const int c[2][2] = {1, 2, {3, 4}};
+  int b[2][3][4] = {1, 2, 3, 4, {5}, {6, 7}, 8};
+  b[1][0][0] = c[1][0] + g[1][0];
+  return sum(g, 2) + b[0][2][1] + b[1][0][0];
+}
@@ -0,0 +1,8 @@
+int main() {
+  const int c = 3, d = c + 1;
+  int x;
+  int y = 2 * d;
+  x = y - c;
+  x = x * 2;
+  return x + y;
+}
@@ -0,0 +1,18 @@
+int fib(int n) {
+  if (n < 2) return n;
+  return fib(n - 1) + fib(n - 2);
+}
+int sum(int a, int b, int c, int d, int e, int f, int g, int h, int i, int j) {
+  return a + b * 2 + c * 3 + d + e + f + g + h * 5 + i + j * 7;
+}
+void nothing() {}
+void early(int x) {
+  if (x) return;
+  nothing();
+}
+int main() {
+  int x = fib(10);
+  nothing();
+  early(x);
+  return x + sum(1, 2, 3, 4, 5, 6, 7, 8, 9, 10);
+}
@@ -0,0 +1,14 @@
+const int N = 10, M = N * 2;
+int g = M + 1, h, k = -3;
+int counter;
+int main() {
+  int g2 = g;
+  h = g2 + N;
+  counter = counter + 1;
+  {
+    int g = 100;
+    h = h + g;
+  }
+  k = k * counter;
+  return h + k + g + M;
+}
@@ -0,0 +1,10 @@
+int main() {
+  int a = 3, b = 0;
+  if (a > 2) b = 1;
+  if (a < 2) b = b + 10; else b = b + 20;
+  if (a == 3)
+    if (b == 0) b = 100;
+    else b = b + 5;
+  if (a) { if (0) return 7; } else return 8;
+  if (b > 100) { return 1; } else { return b; }
+}
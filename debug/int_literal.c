@@ -0,0 +1,11 @@
+int main() {
+  int a = -2147483648;
+  int b = 0xFFFFFFFF;
+  int c = 037777777777;
+  int d = 0x80000000;
+  int e = 2147483647;
+  int f = - -2147483648;
+  putint(a); putch(10); putint(b); putch(10); putint(c); putch(10);
+  putint(d); putch(10); putint(e); putch(10); putint(f); putch(10);
+  return 0;
+}
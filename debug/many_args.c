@@ -0,0 +1,14 @@
+// 前 8 个参数通过 a0-a7 传递, 其余的参数通过栈传递
+int sum10(int a, int b, int c, int d, int e, int f, int g, int h, int i, int j) {
+  return a + b * 2 + c * 3 + d * 4 + e * 5 + f * 6 + g * 7 + h * 8 + i * 9 + j * 10;
+}
+
+int twice(int x) {
+  return sum10(x, x, x, x, x, x, x, x, x, x) * 2;
+}
+
+int main() {
+  int x = twice(1);
+  int y = sum10(10, 9, 8, 7, 6, 5, 4, 3, 2, x);
+  return y - x;
+}
@@ -0,0 +1,20 @@
+int main() {
+  int x = 1;
+  const int k = 5;
+  {
+    int x = 2;
+    x = x + k;
+    {
+      const int k = 100;
+      int y = x + k;
+      x = y;
+      ;
+      x + 1;
+    }
+    {
+      return x * 2;
+    }
+    x = 0;
+  }
+  return x;
+}
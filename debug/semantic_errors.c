@@ -0,0 +1,24 @@
+const int N = 3;
+int a[3][4];
+int b[N - 3];
+const int c[2] = {N, N / 0};
+int d[2] = {1, {2}, 3};
+int e = a[0][0];
+void f(int x[], int y) { return 1; }
+int g() { return; }
+int h(int x[][3]) { return x[1][2]; }
+int main() {
+  N = 4;
+  a[1] = 2;
+  a[1][2][3] = 1;
+  f(a[1], 2);
+  f(a, 2);
+  f(1, 2, 3);
+  h(a);
+  int z = g() + f(a[0], 1);
+  z = u + 1;
+  z = putint;
+  z();
+  break;
+  return a;
+}
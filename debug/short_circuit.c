@@ -0,0 +1,9 @@
+int main() {
+  int a = 0, b = 5, n = 0;
+  if (a && b / a) n = n + 1;
+  if (b || b / a) n = n + 10;
+  if (a != 0 && b / a > 1 || b > 4) n = n + 100;
+  int c = (a || 0) + (b && 7) * 2 + !(a && b);
+  while (a < 3 && (b || 0)) a = a + 1;
+  return n + c + a;
+}
@@ -0,0 +1,17 @@
+// && 和 || 的右侧只有在需要时才会被求值
+int count = 0;
+
+int inc(int ret) {
+  count = count + 1;
+  return ret;
+}
+
+int main() {
+  int r = 0;
+  if (inc(0) && inc(1)) r = r + 1;
+  if (inc(1) || inc(1)) r = r + 2;
+  if (inc(1) && inc(0) || inc(1)) r = r + 4;
+  if (inc(0) || inc(0) && inc(1)) r = r + 8;
+  // count == 7, r == 6
+  return count * 10 + r;
+}
@@ -0,0 +1,7 @@
+// 表达式需要的寄存器多于可分配的寄存器时, 一部分值溢出到栈上
+int main() {
+  int a = 1, b = 2, c = 3;
+  int big[1024];
+  big[1023] = 7;
+  return a + (b + (c + (a * b + (b * c + (c * a + (a + (b + (c + (a * c + (b - (c - (a + (b + big[1023])))))))))))));
+}
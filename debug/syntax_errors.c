@@ -0,0 +1,14 @@
+int g = 3
+int h = 4;
+int f(int x) {
+  int a = 1 +;
+  a = 99999999999;
+  if (a) b = ;
+  int c[2][ = {1};
+  return x + a + c[0][0];
+}
+int main() {
+  int y = f(1;
+  while (y) { y = y - 1; }
+  return 0;
+}
@@ -0,0 +1,33 @@
+const int Z = 0;
+int f(int x) {
+  int unused = 1;
+  if (x) return 1;
+}
+int g(int x) {
+  while (1) {
+    if (x) return x;
+    x = x + 1;
+  }
+}
+int k() {
+  {
+    const int c = 1;
+    while (c) {}
+  }
+}
+int h(int x) {
+  if (x) return 1; else return 2;
+  x = 3;
+  x = 4;
+}
+int main() {
+  int a = 5, b;
+  a = a / 0;
+  a = a % Z;
+  a = a / (Z + 1);
+  while (a) { break; a = 1; }
+  {
+    const int c = 1;
+  }
+  return a;
+}
@@ -0,0 +1,17 @@
+int main() {
+  int i = 0, s = 0;
+  while (i < 10) {
+    i = i + 1;
+    if (i % 3 == 0) continue;
+    int j = 0;
+    while (1) {
+      j = j + 1;
+      if (j > i) break;
+      s = s + j;
+    }
+    if (s > 100) break;
+  }
+  while (0) ;
+  while (s > 200) if (s) s = s - 1; else break;
+  return s;
+}
@@ -0,0 +1,658 @@
+// 语义检查: 在生成 IR 之前检查 AST, 报告所有带位置信息的语义错误
+//
+// 检查标识符的定义和使用、函数调用的实参个数和类型、数组下标的个数、
+// 对常量的赋值、return 与函数返回类型是否一致, 以及 main 函数的定义
+// 同时在编译期求出常量表达式 (数组长度、常量和全局变量的初始值) 的值, 并检查初始化列表的形状
+// 以及报告 warnings 中开启的警告: 未使用的局部变量、不可达的语句、
+// 非 void 函数缺少 return, 以及除数为常量 0
+use crate::ast::*;
+use crate::diagnostic::{Diagnostic, Level};
+use crate::irgen::{flatten_init, Error};
+use crate::symtab::{ConstArray, SymbolTable, SYSY_LIB};
+use crate::warning::{Warning, Warnings};
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+/// 检查整个程序, 返回按位置排序的所有错误和警告
+///
+/// recovered 表示解析时从语法错误中恢复过, 被跳过的代码中可能有定义、使用或 return,
+/// 这时不报告使用未定义的标识符、缺少 main 函数、未使用的变量和缺少 return
+pub fn check(comp_unit: &CompUnit, warnings: &Warnings, recovered: bool) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        symbols: SymbolTable::new(),
+        func: None,
+        loops: 0,
+        infinite_loops: HashSet::new(),
+        in_const: false,
+        recovered,
+        warnings,
+        diags: Vec::new(),
+    };
+    checker.declare_sysy_lib();
+    for item in &comp_unit.items {
+        match item {
+            GlobalItem::Decl(decl) => checker.decl(decl),
+            GlobalItem::FuncDef(func_def) => checker.func_def(func_def),
+        }
+    }
+    checker.check_main(comp_unit);
+    // 未使用的变量在离开作用域时才报告, 没有位置的诊断信息排在最后
+    checker
+        .diags
+        .sort_by_key(|diag| diag.span.map_or(usize::MAX, |span| span.start));
+    checker.diags
+}
+
+// 表达式的类型
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Ty {
+    Int,
+    Void,
+    // 数组, 只能作为函数的实参, 记录除第一维外各维的长度
+    Array(Vec<usize>),
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ty::Int => write!(f, "int"),
+            Ty::Void => write!(f, "void"),
+            Ty::Array(lens) => {
+                write!(f, "int[]")?;
+                for len in lens {
+                    write!(f, "[{}]", len)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Ty {
+    // 各维长度为 lens 的变量的类型, 标量的 lens 为空
+    fn of_lens(lens: &[usize]) -> Self {
+        match lens {
+            [] => Ty::Int,
+            [_, inner @ ..] => Ty::Array(inner.to_vec()),
+        }
+    }
+}
+
+impl From<&FuncType> for Ty {
+    fn from(func_type: &FuncType) -> Self {
+        match func_type {
+            FuncType::Int => Ty::Int,
+            FuncType::Void => Ty::Void,
+        }
+    }
+}
+
+// 标识符对应的对象, 变量、常量和参数记录它们的类型, 数组的长度有错误时为 None
+// 常量同时记录在编译期求出的值, 有错误而无法求出时为 None
+// 函数的形参类型为 None 时不检查对应的实参
+#[derive(Debug, Clone)]
+enum Item {
+    Const(Option<Ty>, Option<Rc<ConstArray>>),
+    Var(Option<Ty>),
+    Param(Option<Ty>),
+    Func { ret: Ty, params: Vec<Option<Ty>> },
+}
+
+#[derive(Debug, Clone)]
+struct Symbol {
+    item: Item,
+    // 定义的位置
+    span: Span,
+    used: bool,
+}
+
+struct Checker<'a> {
+    symbols: SymbolTable<Symbol>,
+    // 当前所在的函数名及其返回类型
+    func: Option<(String, Ty)>,
+    // 当前所在的循环层数
+    loops: usize,
+    // 条件为常量真值且没有 break 的 while 语句的起始位置
+    // 需要在检查语句时求出条件的值, 这时条件中的常量所在的作用域还没有离开
+    infinite_loops: HashSet<usize>,
+    // 是否正在检查常量表达式, 其中除数为 0 是错误, 不再报告警告
+    in_const: bool,
+    // 是否从语法错误中恢复过
+    recovered: bool,
+    warnings: &'a Warnings,
+    diags: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, err: Error, span: Span) {
+        self.diags.push(Diagnostic::from(err).with_span(span));
+    }
+
+    fn error_count(&self) -> usize {
+        self.diags
+            .iter()
+            .filter(|diag| diag.level == Level::Error)
+            .count()
+    }
+
+    // 报告开启的警告, 信息末尾注明对应的命令行选项
+    fn warn(&mut self, warning: Warning, message: String, span: Span) {
+        if !self.warnings.is_enabled(warning) {
+            return;
+        }
+        let diag = if self.warnings.is_error(warning) {
+            Diagnostic::error(format!("{} [-Werror={}]", message, warning.name()))
+        } else {
+            Diagnostic::warning(format!("{} [-W{}]", message, warning.name()))
+        };
+        self.diags.push(diag.with_span(span));
+    }
+
+    fn declare_sysy_lib(&mut self) {
+        for (name, params, func_type) in SYSY_LIB {
+            let params = params
+                .iter()
+                .map(|&is_array| Some(if is_array { Ty::Array(vec![]) } else { Ty::Int }))
+                .collect();
+            let item = Item::Func {
+                ret: Ty::from(&func_type),
+                params,
+            };
+            self.define(name, item, Span::default());
+        }
+    }
+
+    // 在当前作用域中定义标识符, 同一作用域中重复定义时报错
+    fn define(&mut self, ident: &str, item: Item, span: Span) {
+        let symbol = Symbol {
+            item,
+            span,
+            used: false,
+        };
+        if let Err(err) = self.symbols.insert(ident, symbol) {
+            self.error(err, span);
+        }
+    }
+
+    // 离开作用域, 报告其中未使用的局部变量和常量
+    fn exit_scope(&mut self) {
+        let scope = self.symbols.exit_scope();
+        if self.recovered {
+            return;
+        }
+        let mut unused: Vec<_> = scope
+            .into_iter()
+            .filter(|(_, symbol)| {
+                !symbol.used && matches!(symbol.item, Item::Const(..) | Item::Var(_))
+            })
+            .map(|(ident, symbol)| (symbol.span, ident))
+            .collect();
+        unused.sort_by_key(|(span, _)| span.start);
+        for (span, ident) in unused {
+            let message = format!("unused variable `{}`", ident);
+            self.warn(Warning::UnusedVariable, message, span);
+        }
+    }
+
+    // 查找标识符, 不标记为已使用
+    fn find(&self, ident: &str) -> Option<Item> {
+        self.symbols.lookup(ident).ok().map(|symbol| symbol.item)
+    }
+
+    // 查找标识符并标记为已使用, 未定义时报错
+    fn lookup(&mut self, ident: &str, span: Span) -> Option<Item> {
+        match self.symbols.lookup_mut(ident) {
+            Ok(symbol) => {
+                symbol.used = true;
+                Some(symbol.item.clone())
+            }
+            Err(err) => {
+                if !self.recovered {
+                    self.error(err, span);
+                }
+                None
+            }
+        }
+    }
+
+    // 在编译期求表达式的值, 不报告错误
+    fn eval(&self, exp: &Exp) -> Result<i32, Error> {
+        exp.eval_with(&|ident| match self.find(ident) {
+            Some(Item::Const(_, Some(array))) => Ok(array),
+            _ => Err(Error::NotConstant(ident.to_string())),
+        })
+    }
+
+    // 检查常量表达式并求出它的值, 出错时为 None
+    // 表达式中已经报告过错误, 引用了未定义的标识符, 或者引用的常量的值有错误时, 不再报告它不是常量
+    fn const_exp(&mut self, exp: &Exp) -> Option<i32> {
+        let errors = self.error_count();
+        self.in_const = true;
+        let ty = self.exp(exp);
+        self.in_const = false;
+        self.expect(ty, Ty::Int, exp.span());
+        if self.error_count() > errors {
+            return None;
+        }
+        match self.eval(exp) {
+            Ok(value) => Some(value),
+            Err(Error::NotConstant(ident))
+                if matches!(self.find(&ident), None | Some(Item::Const(_, None))) =>
+            {
+                None
+            }
+            Err(err) => {
+                self.error(err, exp.span());
+                None
+            }
+        }
+    }
+
+    // 语句执行后是否一定不会继续执行下一条语句:
+    // 以 return、break、continue 结束, 或者是没有 break 的 while (常量真值)
+    fn terminates(&self, stmt: &Stmt) -> bool {
+        match stmt {
+            Stmt::Return(..) | Stmt::Break(_) | Stmt::Continue(_) => true,
+            Stmt::Block(block) => self.terminates_items(&block.items),
+            Stmt::If {
+                then,
+                else_: Some(else_),
+                ..
+            } => self.terminates(then) && self.terminates(else_),
+            Stmt::While { span, .. } => self.infinite_loops.contains(&span.start),
+            _ => false,
+        }
+    }
+
+    fn terminates_items(&self, items: &[BlockItem]) -> bool {
+        items.iter().any(|item| match item {
+            BlockItem::Stmt(stmt) => self.terminates(stmt),
+            BlockItem::Decl(_) => false,
+        })
+    }
+
+    // 检查表达式的类型是否为 expected, 类型未知 (已经报告过错误) 时不再报告
+    fn expect(&mut self, ty: Option<Ty>, expected: Ty, span: Span) {
+        if let Some(ty) = ty.filter(|ty| *ty != expected) {
+            let err = Error::MismatchedTypes {
+                expected: expected.to_string(),
+                found: ty.to_string(),
+            };
+            self.error(err, span);
+        }
+    }
+
+    fn check_main(&mut self, comp_unit: &CompUnit) {
+        let main = comp_unit.items.iter().find_map(|item| match item {
+            GlobalItem::FuncDef(func_def) if func_def.ident == "main" => Some(func_def),
+            _ => None,
+        });
+        match main {
+            Some(main) => {
+                if !matches!(main.func_type, FuncType::Int) {
+                    self.error(Error::MainReturnType, main.span);
+                }
+                if !main.params.is_empty() {
+                    self.error(Error::MainParams, main.span);
+                }
+            }
+            None if !self.recovered => self.diags.push(Error::NoMain.into()),
+            None => {}
+        }
+    }
+
+    fn func_def(&mut self, func_def: &FuncDef) {
+        let ret = Ty::from(&func_def.func_type);
+        // 数组参数的第一维长度省略, 其余各维的长度是常量表达式
+        let params: Vec<Option<Ty>> = func_def
+            .params
+            .iter()
+            .map(|param| match &param.dims {
+                Some(dims) => self.array_lens(&param.ident, dims).map(Ty::Array),
+                None => Some(Ty::Int),
+            })
+            .collect();
+        // 先定义函数再检查函数体, 以支持递归调用
+        let item = Item::Func {
+            ret: ret.clone(),
+            params: params.clone(),
+        };
+        self.define(&func_def.ident, item, func_def.span);
+
+        // 参数与函数体最外层的定义处于同一个作用域
+        self.symbols.enter_scope();
+        for (param, ty) in func_def.params.iter().zip(params) {
+            self.define(&param.ident, Item::Param(ty), param.span);
+        }
+        self.func = Some((func_def.ident.clone(), ret.clone()));
+        self.items(&func_def.block.items);
+        self.func = None;
+        // 与 C 一样, main 执行到末尾时返回 0
+        let block = &func_def.block;
+        if ret == Ty::Int
+            && func_def.ident != "main"
+            && !self.recovered
+            && !self.terminates_items(&block.items)
+        {
+            let message = format!(
+                "control reaches end of non-void function `{}`",
+                func_def.ident
+            );
+            let end = Span::new(block.span.end - 1, block.span.end);
+            self.warn(Warning::MissingReturn, message, end);
+        }
+        self.exit_scope();
+    }
+
+    // 只对一个块中第一条不可达的语句报告警告
+    fn items(&mut self, items: &[BlockItem]) {
+        let mut terminated = false;
+        let mut warned = false;
+        for item in items {
+            if terminated && !warned {
+                let message = "unreachable statement".to_string();
+                self.warn(Warning::UnreachableCode, message, item_span(item));
+                warned = true;
+            }
+            match item {
+                BlockItem::Decl(decl) => self.decl(decl),
+                BlockItem::Stmt(stmt) => {
+                    self.stmt(stmt);
+                    terminated = terminated || self.terminates(stmt);
+                }
+            }
+        }
+    }
+
+    fn decl(&mut self, decl: &Decl) {
+        match decl {
+            Decl::Const(decl) => {
+                for def in &decl.defs {
+                    let lens = self.array_lens(&def.ident, &def.dims);
+                    let ty = lens.as_deref().map(Ty::of_lens);
+                    let value = self.def(&def.ident, lens, Some(&def.init), true);
+                    self.define(&def.ident, Item::Const(ty, value), def.span);
+                }
+            }
+            Decl::Var(decl) => {
+                for def in &decl.defs {
+                    let lens = self.array_lens(&def.ident, &def.dims);
+                    let ty = lens.as_deref().map(Ty::of_lens);
+                    self.def(&def.ident, lens, def.init.as_ref(), false);
+                    self.define(&def.ident, Item::Var(ty), def.span);
+                }
+            }
+        }
+    }
+
+    // 检查定义的初始值, 常量和全局变量的初始值必须是常量表达式
+    // lens 是各维的长度, 有错误时为 None
+    // 返回常量的值 (标量视为 0 维的数组), 不是常量或者有错误时为 None
+    fn def(
+        &mut self,
+        ident: &str,
+        lens: Option<Vec<usize>>,
+        init: Option<&InitVal>,
+        is_const: bool,
+    ) -> Option<Rc<ConstArray>> {
+        let init = init?;
+        // 标量用表达式初始化, 数组用初始化列表初始化, 展开为数组的全部元素
+        let mut elems = Vec::new();
+        let shape = match (init, &lens) {
+            (InitVal::Exp(exp), Some(lens)) if lens.is_empty() => {
+                elems.push(Some(exp));
+                Ok(())
+            }
+            (InitVal::List(list, _), Some(lens)) if !lens.is_empty() => {
+                flatten_init(list, lens, &mut elems).map_err(Some)
+            }
+            // 数组的长度有错误, 已经报告过
+            (_, None) => Err(None),
+            _ => Err(Some(init.span())),
+        };
+        if let Err(span) = shape {
+            if let Some(span) = span {
+                self.error(Error::InvalidInitializer(ident.to_string()), span);
+            }
+            self.init_val(init);
+            return None;
+        }
+
+        if !is_const && self.func.is_some() {
+            for exp in elems.into_iter().flatten() {
+                let ty = self.exp(exp);
+                self.expect(ty, Ty::Int, exp.span());
+            }
+            return None;
+        }
+        // 先检查全部元素再合并结果, 这样每个元素中的错误都会被报告
+        let values: Vec<Option<i32>> = elems
+            .into_iter()
+            .map(|elem| match elem {
+                Some(exp) => self.const_exp(exp),
+                None => Some(0),
+            })
+            .collect();
+        let values = values.into_iter().collect::<Option<Vec<_>>>()?;
+        let lens = lens.unwrap();
+        is_const.then(|| Rc::new(ConstArray { lens, values }))
+    }
+
+    // 在编译期求出数组各维的长度, 有错误时为 None
+    fn array_lens(&mut self, ident: &str, dims: &[Exp]) -> Option<Vec<usize>> {
+        let lens: Vec<Option<usize>> = dims
+            .iter()
+            .map(|dim| match self.const_exp(dim) {
+                Some(len) if len > 0 => Some(len as usize),
+                Some(_) => {
+                    self.error(Error::InvalidArrayLen(ident.to_string()), dim.span());
+                    None
+                }
+                None => None,
+            })
+            .collect();
+        lens.into_iter().collect()
+    }
+
+    // 初始值中的每个元素都是 int
+    fn init_val(&mut self, init: &InitVal) {
+        match init {
+            InitVal::Exp(exp) => {
+                let ty = self.exp(exp);
+                self.expect(ty, Ty::Int, exp.span());
+            }
+            InitVal::List(list, _) => {
+                for init in list {
+                    self.init_val(init);
+                }
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Assign(lval, exp, _) => {
+                let ty = self.exp(exp);
+                self.expect(ty, Ty::Int, exp.span());
+                if let Some(Item::Const(..)) = self.find(&lval.ident) {
+                    self.error(Error::AssignToConst(lval.ident.clone()), lval.span);
+                    return;
+                }
+                match self.lval(lval) {
+                    Some(Ty::Array(_)) => {
+                        self.error(Error::NotAssignable(lval.ident.clone()), lval.span)
+                    }
+                    ty => self.expect(ty, Ty::Int, lval.span),
+                }
+            }
+            Stmt::Exp(exp, _) => {
+                if let Some(exp) = exp {
+                    self.exp(exp);
+                }
+            }
+            Stmt::Block(block) => {
+                self.symbols.enter_scope();
+                self.items(&block.items);
+                self.exit_scope();
+            }
+            Stmt::If {
+                cond, then, else_, ..
+            } => {
+                self.cond(cond);
+                self.stmt(then);
+                if let Some(else_) = else_ {
+                    self.stmt(else_);
+                }
+            }
+            Stmt::While { cond, body, span } => {
+                self.cond(cond);
+                if self.eval(cond).is_ok_and(|value| value != 0) && !has_break(body) {
+                    self.infinite_loops.insert(span.start);
+                }
+                self.loops += 1;
+                self.stmt(body);
+                self.loops -= 1;
+            }
+            Stmt::Break(span) if self.loops == 0 => self.error(Error::BreakOutsideLoop, *span),
+            Stmt::Continue(span) if self.loops == 0 => {
+                self.error(Error::ContinueOutsideLoop, *span)
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => {}
+            Stmt::Return(exp, span) => {
+                let (func, ret) = self.func.clone().unwrap();
+                match (exp, ret.clone()) {
+                    (Some(exp), Ty::Void) => {
+                        self.exp(exp);
+                        self.error(Error::VoidReturnValue(func), *span);
+                    }
+                    (Some(exp), _) => {
+                        let ty = self.exp(exp);
+                        self.expect(ty, ret, exp.span());
+                    }
+                    (None, Ty::Void) => {}
+                    (None, _) => self.error(Error::MissingReturnValue(func), *span),
+                }
+            }
+        }
+    }
+
+    fn cond(&mut self, cond: &Exp) {
+        let ty = self.exp(cond);
+        self.expect(ty, Ty::Int, cond.span());
+    }
+
+    // 表达式的类型, 出错时为 None
+    fn exp(&mut self, exp: &Exp) -> Option<Ty> {
+        match exp {
+            Exp::Number(..) => Some(Ty::Int),
+            Exp::LVal(lval) => self.lval(lval),
+            Exp::Call(ident, args, span) => self.call(ident, args, *span),
+            Exp::UnaryExp(_, exp, _) => {
+                let ty = self.exp(exp);
+                self.expect(ty, Ty::Int, exp.span());
+                Some(Ty::Int)
+            }
+            Exp::BinaryExp(lhs, op, rhs, span) => {
+                let lhs_ty = self.exp(lhs);
+                self.expect(lhs_ty, Ty::Int, lhs.span());
+                let rhs_ty = self.exp(rhs);
+                self.expect(rhs_ty, Ty::Int, rhs.span());
+                let message = match op {
+                    BinaryOp::Div => "division by zero",
+                    BinaryOp::Mod => "remainder by zero",
+                    _ => return Some(Ty::Int),
+                };
+                if !self.in_const && self.eval(rhs).ok() == Some(0) {
+                    self.warn(Warning::DivisionByZero, message.to_string(), *span);
+                }
+                Some(Ty::Int)
+            }
+        }
+    }
+
+    // 左值的类型: 下标个数少于数组的维数时仍然是数组, 其长度是剩余各维的长度
+    fn lval(&mut self, lval: &LVal) -> Option<Ty> {
+        for index in &lval.indices {
+            let ty = self.exp(index);
+            self.expect(ty, Ty::Int, index.span());
+        }
+        let ty = match self.lookup(&lval.ident, lval.span)? {
+            Item::Const(ty, _) | Item::Var(ty) | Item::Param(ty) => ty?,
+            Item::Func { .. } => {
+                self.error(Error::NotAVariable(lval.ident.clone()), lval.span);
+                return None;
+            }
+        };
+        let (dims, inner) = match ty {
+            Ty::Array(inner) => (inner.len() + 1, inner),
+            _ => (0, vec![]),
+        };
+        match lval.indices.len() {
+            n if n == dims => Some(Ty::Int),
+            n if n < dims => Some(Ty::Array(inner[n..].to_vec())),
+            _ if dims == 0 => {
+                self.error(Error::InvalidSubscript(lval.ident.clone()), lval.span);
+                None
+            }
+            found => {
+                let err = Error::TooManySubscripts {
+                    ident: lval.ident.clone(),
+                    dims,
+                    found,
+                };
+                self.error(err, lval.span);
+                None
+            }
+        }
+    }
+
+    fn call(&mut self, ident: &str, args: &[Exp], span: Span) -> Option<Ty> {
+        let arg_tys: Vec<Option<Ty>> = args.iter().map(|arg| self.exp(arg)).collect();
+        let (ret, params) = match self.lookup(ident, span)? {
+            Item::Func { ret, params } => (ret, params),
+            _ => {
+                self.error(Error::NotAFunction(ident.to_string()), span);
+                return None;
+            }
+        };
+        if args.len() != params.len() {
+            let err = Error::ArgCount {
+                func: ident.to_string(),
+                expected: params.len(),
+                found: args.len(),
+            };
+            self.error(err, span);
+        } else {
+            for ((arg, ty), param) in args.iter().zip(arg_tys).zip(params) {
+                if let Some(param) = param {
+                    self.expect(ty, param, arg.span());
+                }
+            }
+        }
+        Some(ret)
+    }
+}
+
+// 循环体中是否有跳出这个循环的 break
+fn has_break(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Break(_) => true,
+        Stmt::Block(block) => block.items.iter().any(|item| match item {
+            BlockItem::Stmt(stmt) => has_break(stmt),
+            BlockItem::Decl(_) => false,
+        }),
+        Stmt::If { then, else_, .. } => has_break(then) || else_.as_deref().is_some_and(has_break),
+        // 内层循环中的 break 只跳出内层循环
+        _ => false,
+    }
+}
+
+// 块中一项的位置
+fn item_span(item: &BlockItem) -> Span {
+    match item {
+        BlockItem::Stmt(stmt) => stmt.span(),
+        BlockItem::Decl(Decl::Const(decl)) => decl.span,
+        BlockItem::Decl(Decl::Var(decl)) => decl.span,
+    }
+}
@@ -0,0 +1,277 @@
+// 命令行参数的解析
+//
+// 兼容评测脚本使用的 `compiler -koopa|-riscv 输入文件 -o 输出文件`,
+// 以及直接读入 Koopa IR 的 `compiler -koopa-in 输入文件 -o 输出文件`
+use compiler::warning::{Warning, Warnings};
+use std::fmt;
+
+pub const USAGE: &str = "\
+Usage: compiler [OPTIONS] [INPUT]
+
+Compiles a SysY source file to Koopa IR or RISC-V assembly.
+Reads from stdin when INPUT is omitted or `-`.
+
+Options:
+  --emit=STAGES    Comma-separated list of outputs to produce, in pipeline order:
+                   ast, koopa, riscv (default: riscv)
+  -o FILE          Write output to FILE, `-` for stdout (default: stdout)
+  -koopa-in        Treat the input as Koopa IR text instead of SysY
+  -O0, -O2         Optimization level; -O2 uses graph coloring register allocation
+  -W<NAME>         Enable the warning NAME (all warnings are enabled by default):
+                   unused-variable, unreachable-code, missing-return, division-by-zero
+  -Wno-<NAME>      Disable the warning NAME
+  -Werror          Treat warnings as errors
+  -Werror=<NAME>   Enable the warning NAME and treat it as an error
+  -koopa, -riscv   Same as --emit=koopa and --emit=riscv
+  -h, --help       Print this help and exit
+  -V, --version    Print version information and exit
+";
+
+/// 可以输出的编译阶段, 按编译的先后顺序排列
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Emit {
+    Ast,
+    Koopa,
+    Riscv,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    /// 需要输出的阶段, 已排序并去重
+    pub emit: Vec<Emit>,
+    /// 输入文件, None 表示标准输入
+    pub input: Option<String>,
+    /// 输出文件, None 表示标准输出
+    pub output: Option<String>,
+    /// 输入是否为文本形式的 Koopa IR
+    pub koopa_in: bool,
+    pub opt_level: u32,
+    /// 开启的警告
+    pub warnings: Warnings,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Compile(Options),
+    Help,
+    Version,
+}
+
+/// 命令行参数有误
+#[derive(Debug)]
+pub enum Error {
+    UnknownOption(String),
+    UnknownEmit(String),
+    UnknownWarning(String),
+    MissingValue(String),
+    DuplicateOption(String),
+    ExtraInput(String),
+    AstFromKoopa,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownOption(option) => write!(f, "unknown option `{}`", option),
+            Error::UnknownEmit(stage) => write!(
+                f,
+                "unknown emit stage `{}`, expected one of `ast`, `koopa`, `riscv`",
+                stage
+            ),
+            Error::UnknownWarning(name) => write!(f, "unknown warning `{}`", name),
+            Error::MissingValue(option) => write!(f, "option `{}` requires a value", option),
+            Error::DuplicateOption(option) => write!(f, "option `{}` given more than once", option),
+            Error::ExtraInput(input) => write!(f, "unexpected extra input file `{}`", input),
+            Error::AstFromKoopa => write!(f, "cannot emit `ast` when the input is Koopa IR"),
+        }
+    }
+}
+
+/// 解析命令行参数 (不含程序名)
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, Error> {
+    let mut emit = Vec::new();
+    let mut input = None;
+    let mut output = None;
+    let mut koopa_in = false;
+    let mut opt_level = 0;
+    let mut warnings = Warnings::default();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-koopa" => emit.push(Emit::Koopa),
+            "-riscv" => emit.push(Emit::Riscv),
+            "-koopa-in" => koopa_in = true,
+            "-o" => {
+                let file = args
+                    .next()
+                    .ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                if output.replace(file).is_some() {
+                    return Err(Error::DuplicateOption(arg));
+                }
+            }
+            "--emit" => {
+                let stages = args
+                    .next()
+                    .ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                parse_emit(&stages, &mut emit)?;
+            }
+            _ if arg.starts_with("--emit=") => parse_emit(&arg["--emit=".len()..], &mut emit)?,
+            "-Werror" => warnings.werror = true,
+            _ if arg.starts_with("-Werror=") => {
+                warnings.enable_error(parse_warning(&arg["-Werror=".len()..])?)
+            }
+            _ if arg.starts_with("-Wno-") => {
+                warnings.disable(parse_warning(&arg["-Wno-".len()..])?)
+            }
+            _ if arg.starts_with("-W") => warnings.enable(parse_warning(&arg["-W".len()..])?),
+            _ if arg.starts_with("-O") => {
+                opt_level = arg[2..]
+                    .parse()
+                    .map_err(|_| Error::UnknownOption(arg.clone()))?;
+            }
+            // 单独的 - 表示标准输入
+            _ if arg.starts_with('-') && arg != "-" => return Err(Error::UnknownOption(arg)),
+            _ => {
+                if input.is_some() {
+                    return Err(Error::ExtraInput(arg));
+                }
+                input = Some(arg);
+            }
+        }
+    }
+
+    if emit.is_empty() {
+        emit.push(Emit::Riscv);
+    }
+    emit.sort_unstable();
+    emit.dedup();
+    if koopa_in && emit.contains(&Emit::Ast) {
+        return Err(Error::AstFromKoopa);
+    }
+    Ok(Command::Compile(Options {
+        emit,
+        input: input.filter(|input| input != "-"),
+        output: output.filter(|output| output != "-"),
+        koopa_in,
+        opt_level,
+        warnings,
+    }))
+}
+
+// 解析 --emit 的值, 如 ast,koopa
+fn parse_emit(stages: &str, emit: &mut Vec<Emit>) -> Result<(), Error> {
+    for stage in stages.split(',') {
+        emit.push(match stage {
+            "ast" => Emit::Ast,
+            "koopa" => Emit::Koopa,
+            "riscv" => Emit::Riscv,
+            _ => return Err(Error::UnknownEmit(stage.to_string())),
+        });
+    }
+    Ok(())
+}
+
+fn parse_warning(name: &str) -> Result<Warning, Error> {
+    Warning::from_name(name).ok_or_else(|| Error::UnknownWarning(name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, Error> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn parse_options(args: &[&str]) -> Options {
+        match parse(args) {
+            Ok(Command::Compile(options)) => options,
+            result => panic!("expected options for {:?}, got {:?}", args, result),
+        }
+    }
+
+    #[test]
+    fn legacy_form() {
+        let options = parse_options(&["-koopa", "in.c", "-o", "out.koopa"]);
+        assert_eq!(options.emit, [Emit::Koopa]);
+        assert_eq!(options.input.as_deref(), Some("in.c"));
+        assert_eq!(options.output.as_deref(), Some("out.koopa"));
+        assert!(!options.koopa_in);
+        assert_eq!(options.opt_level, 0);
+
+        let options = parse_options(&["-riscv", "in.koopa", "-koopa-in", "-O2", "-o", "out.S"]);
+        assert_eq!(options.emit, [Emit::Riscv]);
+        assert!(options.koopa_in);
+        assert_eq!(options.opt_level, 2);
+    }
+
+    #[test]
+    fn emit_list() {
+        // 按编译的先后顺序输出, 重复的阶段只输出一次
+        let options = parse_options(&["--emit=riscv,ast", "--emit", "koopa,ast"]);
+        assert_eq!(options.emit, [Emit::Ast, Emit::Koopa, Emit::Riscv]);
+        // 默认只输出汇编
+        assert_eq!(parse_options(&["in.c"]).emit, [Emit::Riscv]);
+    }
+
+    #[test]
+    fn stdin_and_stdout() {
+        let options = parse_options(&["-o", "-", "-"]);
+        assert_eq!(options.input, None);
+        assert_eq!(options.output, None);
+        let options = parse_options(&[]);
+        assert_eq!(options.input, None);
+        assert_eq!(options.output, None);
+    }
+
+    #[test]
+    fn warnings() {
+        let options = parse_options(&["-Wno-unused-variable", "-Werror"]);
+        assert!(!options.warnings.is_enabled(Warning::UnusedVariable));
+        assert!(options.warnings.is_enabled(Warning::MissingReturn));
+        assert!(options.warnings.werror);
+        assert!(options.warnings.is_error(Warning::DivisionByZero));
+
+        // -Werror=<名字> 重新开启被关闭的警告, 只把它当作错误
+        let options = parse_options(&["-Wno-unused-variable", "-Werror=unused-variable"]);
+        assert!(options.warnings.is_enabled(Warning::UnusedVariable));
+        assert!(options.warnings.is_error(Warning::UnusedVariable));
+        assert!(!options.warnings.is_error(Warning::MissingReturn));
+        assert!(!options.warnings.werror);
+        assert!(matches!(
+            parse(&["-Werror=foo"]),
+            Err(Error::UnknownWarning(name)) if name == "foo"
+        ));
+    }
+
+    #[test]
+    fn help_and_version() {
+        // 之后的参数不再检查
+        assert!(matches!(parse(&["--help", "-x"]), Ok(Command::Help)));
+        assert!(matches!(parse(&["-V"]), Ok(Command::Version)));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(parse(&["-x"]), Err(Error::UnknownOption(opt)) if opt == "-x"));
+        assert!(matches!(parse(&["-Ofast"]), Err(Error::UnknownOption(_))));
+        assert!(
+            matches!(parse(&["--emit=llvm"]), Err(Error::UnknownEmit(stage)) if stage == "llvm")
+        );
+        assert!(matches!(parse(&["-Wfoo"]), Err(Error::UnknownWarning(name)) if name == "foo"));
+        assert!(matches!(parse(&["in.c", "-o"]), Err(Error::MissingValue(opt)) if opt == "-o"));
+        assert!(matches!(parse(&["--emit"]), Err(Error::MissingValue(_))));
+        assert!(matches!(
+            parse(&["-o", "a", "-o", "b"]),
+            Err(Error::DuplicateOption(opt)) if opt == "-o"
+        ));
+        assert!(matches!(parse(&["a.c", "b.c"]), Err(Error::ExtraInput(input)) if input == "b.c"));
+        assert!(matches!(
+            parse(&["-koopa-in", "--emit=ast"]),
+            Err(Error::AstFromKoopa)
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+// 编译错误和警告的诊断信息
+//
+// 输出的格式如下, 没有位置信息时只输出错误信息和文件名:
+//   error: unexpected token `;`
+//    --> test.c:1:24
+//     |
+//   1 | int main() { return 1 +; }
+//     |                        ^
+//     = expected one of `!`, `(`, `+`, `-`, identifier, integer literal
+use crate::ast::Span;
+use crate::source_map::SourceMap;
+use lalrpop_util::{ErrorRecovery, ParseError};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Error => write!(f, "error"),
+            Level::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    /// 出错的代码在源文件中的位置
+    pub span: Option<Span>,
+    /// 附加的说明, 如期望的 token
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Diagnostic::new(Level::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Diagnostic::new(Level::Warning, message)
+    }
+
+    pub fn new(level: Level, message: impl Into<String>) -> Self {
+        Diagnostic {
+            level,
+            message: message.into(),
+            span: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    // 附加期望的 token 列表
+    fn with_expected(self, expected: &[String]) -> Self {
+        let mut names: Vec<String> = Vec::new();
+        for name in expected.iter().map(|name| token_name(name)) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        match names.len() {
+            0 => self,
+            1 => self.with_note(format!("expected {}", names[0])),
+            _ => self.with_note(format!("expected one of {}", names.join(", "))),
+        }
+    }
+
+    /// 生成诊断信息的文本
+    pub fn render(&self, map: &SourceMap) -> String {
+        let mut result = format!("{}: {}\n", self.level, self.message);
+        match self.span {
+            Some(span) => {
+                let (line, col) = map.line_col(span.start);
+                let text = map.line(line);
+                let gutter = " ".repeat(line.to_string().len());
+                result.push_str(&format!("{}--> {}:{}:{}\n", gutter, map.name(), line, col));
+                result.push_str(&format!("{} |\n", gutter));
+                result.push_str(&format!("{} | {}\n", line, text));
+                // ^ 与出错的代码对齐: 之前的制表符原样保留, 其他字符换成空格
+                let prefix: String = text
+                    .chars()
+                    .take(col - 1)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                // 跨越多行时只标出第一行的部分
+                let len = map.first_line_of(span).chars().count().max(1);
+                result.push_str(&format!("{} | {}{}\n", gutter, prefix, "^".repeat(len)));
+                for note in &self.notes {
+                    result.push_str(&format!("{} = {}\n", gutter, note));
+                }
+            }
+            None => {
+                result.push_str(&format!(" --> {}\n", map.name()));
+                for note in &self.notes {
+                    result.push_str(&format!("  = {}\n", note));
+                }
+            }
+        }
+        result
+    }
+}
+
+/// 整数字面量 value 超出范围, 在 parser 的动作中记录错误, 返回代替它的值
+pub fn literal_too_large<T>(
+    errors: &mut Vec<ErrorRecovery<usize, T, Diagnostic>>,
+    value: i64,
+    start: usize,
+    end: usize,
+) -> i32 {
+    let mut error = Diagnostic::error("integer literal is too large for `int`")
+        .with_span(Span::new(start, end));
+    // 2147483648 只能紧跟在负号之后
+    if value == 1 << 31 {
+        error = error.with_note("only `-2147483648` can be written with this literal");
+    }
+    errors.push(ErrorRecovery {
+        error: ParseError::User { error },
+        dropped_tokens: Vec::new(),
+    });
+    0
+}
+
+/// 把 lalrpop 生成的 parser 报告的错误转换为诊断信息
+pub fn from_parse_error<T: fmt::Display>(err: ParseError<usize, T, Diagnostic>) -> Diagnostic {
+    match err {
+        ParseError::InvalidToken { location } => {
+            Diagnostic::error("invalid token").with_span(Span::new(location, location + 1))
+        }
+        ParseError::UnrecognizedEof { location, expected } => {
+            Diagnostic::error("unexpected end of file")
+                .with_span(Span::new(location, location))
+                .with_expected(&expected)
+        }
+        ParseError::UnrecognizedToken {
+            token: (start, token, end),
+            expected,
+        } => Diagnostic::error(format!("unexpected token `{}`", token))
+            .with_span(Span::new(start, end))
+            .with_expected(&expected),
+        ParseError::ExtraToken {
+            token: (start, token, end),
+        } => Diagnostic::error(format!("unexpected extra token `{}`", token))
+            .with_span(Span::new(start, end)),
+        ParseError::User { error } => error,
+    }
+}
+
+// lalrpop 中终结符的名字: 字符串为 "xxx", 正则表达式为 r#"xxx"#
+// 标识符和整数字面量的正则表达式换成更易读的名字
+fn token_name(name: &str) -> String {
+    match name {
+        r##"r#"[_a-zA-Z][_a-zA-Z0-9]*"#"## => "identifier".to_string(),
+        r##"r#"[1-9][0-9]*"#"## | r##"r#"0[0-7]*"#"## | r##"r#"0[xX][0-9a-fA-F]+"#"## => {
+            "integer literal".to_string()
+        }
+        _ => match name
+            .strip_prefix('"')
+            .and_then(|name| name.strip_suffix('"'))
+        {
+            Some(literal) => format!("`{}`", literal),
+            None => name.to_string(),
+        },
+    }
+}
@@ -0,0 +1,78 @@
+// 编译期求值常量表达式
+// 用于 const 定义的初始值, 以及之后数组的长度
+use crate::ast::*;
+use crate::irgen::Error;
+use crate::symtab::{ConstArray, Symbol, SymbolTable};
+use std::rc::Rc;
+
+impl Exp {
+    /// 在编译期求出表达式的值, 运算按 i32 的补码语义回绕
+    ///
+    /// 表达式中只能引用已经定义的常量和常量数组的元素, 引用变量时报告 `Error::NotConstant`
+    pub fn eval(&self, symbols: &SymbolTable) -> Result<i32, Error> {
+        self.eval_with(&|ident| match symbols.lookup(ident)? {
+            Symbol::Const(value) => Ok(Rc::new(ConstArray::scalar(value))),
+            Symbol::ConstArray(_, array) => Ok(array),
+            _ => Err(Error::NotConstant(ident.to_string())),
+        })
+    }
+
+    /// 与 `eval` 相同, 但由 lookup 给出表达式中引用的常量的值, 标量常量视为 0 维的数组
+    pub fn eval_with<F>(&self, lookup: &F) -> Result<i32, Error>
+    where
+        F: Fn(&str) -> Result<Rc<ConstArray>, Error>,
+    {
+        match self {
+            Exp::Number(n, _) => Ok(*n),
+            // 只能引用标量常量和常量数组的元素, 不能引用常量数组本身或它的一部分
+            Exp::LVal(lval) => {
+                let array = lookup(&lval.ident)?;
+                if lval.indices.len() != array.lens.len() {
+                    return Err(Error::NotConstant(lval.ident.clone()));
+                }
+                let mut indices = Vec::new();
+                for index in &lval.indices {
+                    indices.push(index.eval_with(lookup)?);
+                }
+                array
+                    .get(&indices)
+                    .ok_or_else(|| Error::IndexOutOfBounds(lval.ident.clone()))
+            }
+            Exp::Call(ident, _, _) => Err(Error::NotConstant(ident.clone())),
+            Exp::UnaryExp(op, exp, _) => {
+                let value = exp.eval_with(lookup)?;
+                Ok(match op {
+                    UnaryOp::Pos => value,
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::Not => (value == 0) as i32,
+                })
+            }
+            // && 和 || 与运行时一样短路, 右侧不会被求值
+            Exp::BinaryExp(lhs, BinaryOp::And, rhs, _) => {
+                Ok((lhs.eval_with(lookup)? != 0 && rhs.eval_with(lookup)? != 0) as i32)
+            }
+            Exp::BinaryExp(lhs, BinaryOp::Or, rhs, _) => {
+                Ok((lhs.eval_with(lookup)? != 0 || rhs.eval_with(lookup)? != 0) as i32)
+            }
+            Exp::BinaryExp(lhs, op, rhs, _) => {
+                let lhs = lhs.eval_with(lookup)?;
+                let rhs = rhs.eval_with(lookup)?;
+                Ok(match op {
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div | BinaryOp::Mod if rhs == 0 => return Err(Error::DivisionByZero),
+                    BinaryOp::Div => lhs.wrapping_div(rhs),
+                    BinaryOp::Mod => lhs.wrapping_rem(rhs),
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Eq => (lhs == rhs) as i32,
+                    BinaryOp::Ne => (lhs != rhs) as i32,
+                    BinaryOp::Lt => (lhs < rhs) as i32,
+                    BinaryOp::Gt => (lhs > rhs) as i32,
+                    BinaryOp::Le => (lhs <= rhs) as i32,
+                    BinaryOp::Ge => (lhs >= rhs) as i32,
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                })
+            }
+        }
+    }
+}
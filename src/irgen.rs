@@ -0,0 +1,1045 @@
+// 由 AST 直接构建内存形式的 Koopa IR
+// 不再经过 Display 输出文本再由 koopa::front::Driver 解析的过程
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::symtab::{ConstArray, Symbol, SymbolTable, SYSY_LIB};
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Type, TypeKind, Value, ValueKind};
+use std::fmt;
+use std::rc::Rc;
+
+/// 将整个编译单元翻译为 Koopa IR 程序
+pub fn generate_program(comp_unit: &CompUnit) -> Result<Program, Error> {
+    let mut ctx = Context::new();
+    comp_unit.generate(&mut ctx)?;
+    Ok(ctx.program)
+}
+
+/// 语义错误
+///
+/// 语义检查报告所有的错误并附上位置, 生成 IR 时只报告遇到的第一个错误
+#[derive(Debug)]
+pub enum Error {
+    UndeclaredIdent(String),
+    // 同一作用域中重复定义
+    Redeclared(String),
+    // 把函数当作变量使用
+    NotAVariable(String),
+    // 调用的不是函数
+    NotAFunction(String),
+    // 常量表达式中引用了变量
+    NotConstant(String),
+    AssignToConst(String),
+    // 数组长度不是正整数
+    InvalidArrayLen(String),
+    // 初始化列表与变量的类型不匹配, 或者元素过多
+    InvalidInitializer(String),
+    // 对非数组使用下标, 或者下标个数多于数组的维数
+    InvalidSubscript(String),
+    // 常量表达式中常量数组的下标越界
+    IndexOutOfBounds(String),
+    // 给数组整体赋值
+    NotAssignable(String),
+    // 下标个数多于数组的维数
+    TooManySubscripts {
+        ident: String,
+        dims: usize,
+        found: usize,
+    },
+    // 实参个数与形参个数不同
+    ArgCount {
+        func: String,
+        expected: usize,
+        found: usize,
+    },
+    MismatchedTypes {
+        expected: String,
+        found: String,
+    },
+    // void 函数中的 return 带有返回值
+    VoidReturnValue(String),
+    // 非 void 函数中的 return 没有返回值
+    MissingReturnValue(String),
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    DivisionByZero,
+    NoMain,
+    MainReturnType,
+    MainParams,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UndeclaredIdent(ident) => write!(f, "use of undeclared identifier `{}`", ident),
+            Error::Redeclared(ident) => write!(f, "redefinition of `{}`", ident),
+            Error::NotAVariable(ident) => {
+                write!(f, "function `{}` cannot be used as a variable", ident)
+            }
+            Error::NotAFunction(ident) => write!(f, "called object `{}` is not a function", ident),
+            Error::NotConstant(ident) => {
+                write!(
+                    f,
+                    "`{}` is not a constant and cannot be used in a constant expression",
+                    ident
+                )
+            }
+            Error::AssignToConst(ident) => write!(f, "cannot assign to constant `{}`", ident),
+            Error::InvalidArrayLen(ident) => {
+                write!(f, "size of array `{}` must be a positive integer", ident)
+            }
+            Error::InvalidInitializer(ident) => write!(f, "invalid initializer for `{}`", ident),
+            Error::InvalidSubscript(ident) => {
+                write!(f, "subscripted value `{}` is not an array", ident)
+            }
+            Error::IndexOutOfBounds(ident) => {
+                write!(f, "index out of bounds for constant array `{}`", ident)
+            }
+            Error::NotAssignable(ident) => write!(f, "array `{}` is not assignable", ident),
+            Error::TooManySubscripts { ident, dims, found } => write!(
+                f,
+                "too many subscripts for `{}`: it has {} dimension{}, but {} were given",
+                ident,
+                dims,
+                if *dims == 1 { "" } else { "s" },
+                found
+            ),
+            Error::ArgCount {
+                func,
+                expected,
+                found,
+            } => write!(
+                f,
+                "function `{}` takes {} argument{}, but {} {} given",
+                func,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                found,
+                if *found == 1 { "was" } else { "were" }
+            ),
+            Error::MismatchedTypes { expected, found } => write!(
+                f,
+                "mismatched types: expected `{}`, found `{}`",
+                expected, found
+            ),
+            Error::VoidReturnValue(func) => {
+                write!(f, "void function `{}` should not return a value", func)
+            }
+            Error::MissingReturnValue(func) => {
+                write!(f, "non-void function `{}` should return a value", func)
+            }
+            Error::BreakOutsideLoop => write!(f, "`break` statement not within a loop"),
+            Error::ContinueOutsideLoop => write!(f, "`continue` statement not within a loop"),
+            Error::DivisionByZero => write!(f, "division by zero in constant expression"),
+            Error::NoMain => write!(f, "no `main` function is defined"),
+            Error::MainReturnType => write!(f, "`main` must return `int`"),
+            Error::MainParams => write!(f, "`main` must not take parameters"),
+        }
+    }
+}
+
+// 位置信息由报告错误的地方用 with_span 附上
+impl From<Error> for Diagnostic {
+    fn from(err: Error) -> Self {
+        Diagnostic::error(err.to_string())
+    }
+}
+
+/// 生成 IR 时的上下文: 正在构建的程序, 以及当前所在的函数和基本块
+///
+/// 临时值的编号由上下文按函数维护, 进入新函数时从 0 开始,
+/// 因此同一个 AST 无论生成多少次、在哪个线程生成, 编号都相同
+struct Context {
+    program: Program,
+    func: Option<Function>,
+    bb: Option<BasicBlock>,
+    next_temp: usize,
+    // 基本块名的编号, 保证同一函数中的基本块不重名
+    next_bb: usize,
+    symbols: SymbolTable,
+    // 外层循环的 (条件判断, 出口) 基本块, 分别是 continue 和 break 的目标
+    loops: Vec<(BasicBlock, BasicBlock)>,
+}
+
+impl Context {
+    fn new() -> Self {
+        Context {
+            program: Program::new(),
+            func: None,
+            bb: None,
+            next_temp: 0,
+            next_bb: 0,
+            symbols: SymbolTable::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    // 开始生成一个新函数, 重置临时值编号
+    fn enter_func(&mut self, func: Function) {
+        self.func = Some(func);
+        self.next_temp = 0;
+        self.next_bb = 0;
+    }
+
+    // 值的类型, 全局变量的数据不在函数的 dfg 中
+    fn value_type(&self, value: Value) -> Type {
+        if value.is_global() {
+            self.program.borrow_value(value).ty().clone()
+        } else {
+            let func = self.program.func(self.func.unwrap());
+            func.dfg().value(value).ty().clone()
+        }
+    }
+
+    // 指针所指向的类型
+    fn pointee_type(&self, ptr: Value) -> Type {
+        match self.value_type(ptr).kind() {
+            TypeKind::Pointer(base) => base.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    // 当前函数的数据, 只能在函数体内调用
+    fn func_data(&mut self) -> &mut FunctionData {
+        self.program.func_mut(self.func.unwrap())
+    }
+
+    // 在当前函数中新建一个基本块, 名字形如 %then_N
+    // 新建的基本块在 enter_bb 时才加入 layout, 这样 layout 中的顺序与生成顺序一致
+    fn new_bb(&mut self, name: &str) -> BasicBlock {
+        let name = format!("%{}_{}", name, self.next_bb);
+        self.next_bb += 1;
+        self.func_data().dfg_mut().new_bb().basic_block(Some(name))
+    }
+
+    // 把基本块追加到 layout 末尾, 之后的指令都插入到这个基本块中
+    fn enter_bb(&mut self, bb: BasicBlock) {
+        self.func_data()
+            .layout_mut()
+            .bbs_mut()
+            .push_key_back(bb)
+            .unwrap();
+        self.bb = Some(bb);
+    }
+
+    // 当前基本块还没有结束时, 跳转到 target
+    fn jump_to(&mut self, target: BasicBlock) {
+        if !self.is_terminated() {
+            let jump = self.func_data().dfg_mut().new_value().jump(target);
+            self.push_inst(jump);
+        }
+    }
+
+    // 当前基本块是否已经以 ret/br/jump 结束
+    fn is_terminated(&mut self) -> bool {
+        let bb = self.bb.unwrap();
+        let func_data = self.func_data();
+        match func_data.layout_mut().bb_mut(bb).insts().back_key() {
+            Some(&inst) => matches!(
+                func_data.dfg().value(inst).kind(),
+                ValueKind::Return(_) | ValueKind::Jump(_) | ValueKind::Branch(_)
+            ),
+            None => false,
+        }
+    }
+
+    // 把指令追加到当前基本块末尾, 有返回值且未命名的指令按顺序编号为 %N
+    fn push_inst(&mut self, inst: Value) {
+        let bb = self.bb.unwrap();
+        let data = self.func_data().dfg().value(inst);
+        if !data.ty().is_unit() && data.name().is_none() {
+            let name = format!("%{}", self.next_temp);
+            self.next_temp += 1;
+            self.func_data().dfg_mut().set_value_name(inst, Some(name));
+        }
+        self.func_data()
+            .layout_mut()
+            .bb_mut(bb)
+            .insts_mut()
+            .push_key_back(inst)
+            .unwrap();
+    }
+}
+
+// 根据 AST 生成内存形式 Koopa IR
+trait GenerateIR {
+    type Output;
+    fn generate(&self, ctx: &mut Context) -> Result<Self::Output, Error>;
+}
+
+impl GenerateIR for CompUnit {
+    type Output = ();
+    fn generate(&self, ctx: &mut Context) -> Result<(), Error> {
+        declare_sysy_lib(ctx)?;
+        for item in &self.items {
+            match item {
+                GlobalItem::Decl(decl) => decl.generate(ctx)?,
+                GlobalItem::FuncDef(func_def) => func_def.generate(ctx)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// 声明 SysY 运行时库中的函数, 用户代码无需声明即可调用
+// 数组参数的类型是 *i32
+fn declare_sysy_lib(ctx: &mut Context) -> Result<(), Error> {
+    for (name, params, func_type) in SYSY_LIB {
+        let params_ty = params
+            .iter()
+            .map(|&is_array| match is_array {
+                true => Type::get_pointer(Type::get_i32()),
+                false => Type::get_i32(),
+            })
+            .collect();
+        let ret_ty = match func_type {
+            FuncType::Int => Type::get_i32(),
+            FuncType::Void => Type::get_unit(),
+        };
+        let func_data = FunctionData::new_decl(format!("@{}", name), params_ty, ret_ty);
+        let func = ctx.program.new_func(func_data);
+        ctx.symbols.insert(name, Symbol::Func(func))?;
+    }
+    Ok(())
+}
+
+impl GenerateIR for FuncDef {
+    type Output = ();
+    fn generate(&self, ctx: &mut Context) -> Result<(), Error> {
+        let ret_ty = match self.func_type {
+            FuncType::Int => Type::get_i32(),
+            FuncType::Void => Type::get_unit(),
+        };
+        // 数组参数的类型是指向其元素的指针, 如 int a[][3] 为 *[i32, 3]
+        let mut param_types = Vec::new();
+        for param in &self.params {
+            param_types.push(match &param.dims {
+                Some(dims) => Type::get_pointer(array_type(&array_lens(ctx, &param.ident, dims)?)),
+                None => Type::get_i32(),
+            });
+        }
+        let params = self
+            .params
+            .iter()
+            .zip(&param_types)
+            .map(|(param, ty)| (Some(format!("@{}", param.ident)), ty.clone()))
+            .collect();
+        let func_data = FunctionData::with_param_names(format!("@{}", self.ident), params, ret_ty);
+        let func = ctx.program.new_func(func_data);
+        // 先加入符号表再生成函数体, 以支持递归调用
+        ctx.symbols.insert(&self.ident, Symbol::Func(func))?;
+        ctx.enter_func(func);
+        let entry = ctx
+            .func_data()
+            .dfg_mut()
+            .new_bb()
+            .basic_block(Some("%entry".to_string()));
+        ctx.enter_bb(entry);
+
+        // 参数与函数体最外层的定义处于同一个作用域
+        // 每个参数都复制到 alloc 中, 之后和普通变量一样使用
+        ctx.symbols.enter_scope();
+        for (i, (param, ty)) in self.params.iter().zip(param_types).enumerate() {
+            let value = ctx.func_data().params()[i];
+            let alloc = alloc_local(ctx, &param.ident, ty);
+            let store = ctx.func_data().dfg_mut().new_value().store(value, alloc);
+            ctx.push_inst(store);
+            let symbol = match param.dims {
+                Some(_) => Symbol::Array(alloc),
+                None => Symbol::Var(alloc),
+            };
+            ctx.symbols.insert(&param.ident, symbol)?;
+        }
+        generate_items(ctx, &self.block.items)?;
+        ctx.symbols.exit_scope();
+
+        // 控制流到达函数末尾时默认返回 0 (void 函数直接返回)
+        if !ctx.is_terminated() {
+            let value = match self.func_type {
+                FuncType::Int => Some(ctx.func_data().dfg_mut().new_value().integer(0)),
+                FuncType::Void => None,
+            };
+            let ret = ctx.func_data().dfg_mut().new_value().ret(value);
+            ctx.push_inst(ret);
+        }
+        ctx.func = None;
+        ctx.bb = None;
+        Ok(())
+    }
+}
+
+impl GenerateIR for Block {
+    type Output = ();
+    fn generate(&self, ctx: &mut Context) -> Result<(), Error> {
+        ctx.symbols.enter_scope();
+        generate_items(ctx, &self.items)?;
+        ctx.symbols.exit_scope();
+        Ok(())
+    }
+}
+
+// 在当前作用域中依次生成块内的定义和语句
+fn generate_items(ctx: &mut Context, items: &[BlockItem]) -> Result<(), Error> {
+    for item in items {
+        // return 之后的语句不可达, 不再生成
+        if ctx.is_terminated() {
+            break;
+        }
+        match item {
+            BlockItem::Decl(decl) => decl.generate(ctx)?,
+            BlockItem::Stmt(stmt) => stmt.generate(ctx)?,
+        }
+    }
+    Ok(())
+}
+
+impl GenerateIR for Decl {
+    type Output = ();
+    fn generate(&self, ctx: &mut Context) -> Result<(), Error> {
+        match self {
+            Decl::Const(decl) => {
+                for def in &decl.defs {
+                    if !def.dims.is_empty() {
+                        // 常量数组和变量数组一样存放在内存中, 但元素必须是常量表达式
+                        declare_array(ctx, &def.ident, &def.dims, Some(&def.init), true)?;
+                        continue;
+                    }
+                    // 标量常量在编译期求值, 不生成任何指令
+                    let value = match &def.init {
+                        InitVal::Exp(exp) => exp.eval(&ctx.symbols)?,
+                        InitVal::List(..) => {
+                            return Err(Error::InvalidInitializer(def.ident.clone()))
+                        }
+                    };
+                    ctx.symbols.insert(&def.ident, Symbol::Const(value))?;
+                }
+            }
+            Decl::Var(decl) => {
+                for def in &decl.defs {
+                    if !def.dims.is_empty() {
+                        declare_array(ctx, &def.ident, &def.dims, def.init.as_ref(), false)?;
+                        continue;
+                    }
+                    let init = match &def.init {
+                        Some(InitVal::Exp(exp)) => Some(exp),
+                        Some(InitVal::List(..)) => {
+                            return Err(Error::InvalidInitializer(def.ident.clone()))
+                        }
+                        None => None,
+                    };
+                    let alloc = if ctx.func.is_none() {
+                        // 全局变量的初始值必须是常量表达式, 没有初始值时为 0
+                        let init = match init {
+                            Some(exp) => {
+                                let value = exp.eval(&ctx.symbols)?;
+                                ctx.program.new_value().integer(value)
+                            }
+                            None => ctx.program.new_value().zero_init(Type::get_i32()),
+                        };
+                        global_alloc(ctx, &def.ident, init)
+                    } else {
+                        let init = match init {
+                            Some(exp) => Some(exp.generate(ctx)?),
+                            None => None,
+                        };
+                        let alloc = alloc_local(ctx, &def.ident, Type::get_i32());
+                        if let Some(init) = init {
+                            let store = ctx.func_data().dfg_mut().new_value().store(init, alloc);
+                            ctx.push_inst(store);
+                        }
+                        alloc
+                    };
+                    ctx.symbols.insert(&def.ident, Symbol::Var(alloc))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// 为局部变量分配栈上空间 (alloc)
+fn alloc_local(ctx: &mut Context, ident: &str, ty: Type) -> Value {
+    let alloc = ctx.func_data().dfg_mut().new_value().alloc(ty);
+    ctx.func_data()
+        .dfg_mut()
+        .set_value_name(alloc, Some(format!("@{}", ident)));
+    ctx.push_inst(alloc);
+    alloc
+}
+
+// 生成全局变量 (global alloc)
+fn global_alloc(ctx: &mut Context, ident: &str, init: Value) -> Value {
+    let alloc = ctx.program.new_value().global_alloc(init);
+    ctx.program
+        .set_value_name(alloc, Some(format!("@{}", ident)));
+    alloc
+}
+
+// 在编译期求出数组各维的长度
+fn array_lens(ctx: &Context, ident: &str, dims: &[Exp]) -> Result<Vec<usize>, Error> {
+    let mut lens = Vec::new();
+    for dim in dims {
+        let len = dim.eval(&ctx.symbols)?;
+        if len <= 0 {
+            return Err(Error::InvalidArrayLen(ident.to_string()));
+        }
+        lens.push(len as usize);
+    }
+    Ok(lens)
+}
+
+// 各维长度对应的数组类型, 如 [2, 3] 为 [[i32, 3], 2]
+fn array_type(lens: &[usize]) -> Type {
+    lens.iter()
+        .rev()
+        .fold(Type::get_i32(), |ty, &len| Type::get_array(ty, len))
+}
+
+// 定义数组: 全局数组的初始值为 aggregate,
+// 局部数组有初始值时先用 zeroinit 整体清零, 再逐个 store 显式给出的元素
+fn declare_array(
+    ctx: &mut Context,
+    ident: &str,
+    dims: &[Exp],
+    init: Option<&InitVal>,
+    is_const: bool,
+) -> Result<(), Error> {
+    let lens = array_lens(ctx, ident, dims)?;
+    let elems = match init {
+        Some(InitVal::List(list, _)) => {
+            let mut elems = Vec::new();
+            flatten_init(list, &lens, &mut elems)
+                .map_err(|_| Error::InvalidInitializer(ident.to_string()))?;
+            Some(elems)
+        }
+        Some(InitVal::Exp(_)) => return Err(Error::InvalidInitializer(ident.to_string())),
+        None => None,
+    };
+
+    // 常量数组和全局数组的元素都是常量表达式, 在编译期求值
+    let values = match &elems {
+        Some(elems) if is_const || ctx.func.is_none() => {
+            let mut values = Vec::new();
+            for elem in elems {
+                values.push(match elem {
+                    Some(exp) => exp.eval(&ctx.symbols)?,
+                    None => 0,
+                });
+            }
+            Some(values)
+        }
+        _ => None,
+    };
+
+    let alloc = if ctx.func.is_none() {
+        let init = match &values {
+            Some(values) => global_aggregate(ctx, values, &lens),
+            None => ctx.program.new_value().zero_init(array_type(&lens)),
+        };
+        global_alloc(ctx, ident, init)
+    } else {
+        let alloc = alloc_local(ctx, ident, array_type(&lens));
+        if let Some(elems) = &elems {
+            if elems.iter().any(Option::is_none) {
+                let zero = ctx
+                    .func_data()
+                    .dfg_mut()
+                    .new_value()
+                    .zero_init(array_type(&lens));
+                let store = ctx.func_data().dfg_mut().new_value().store(zero, alloc);
+                ctx.push_inst(store);
+            }
+            for (i, elem) in elems.iter().enumerate() {
+                let value = match (elem, &values) {
+                    (None, _) => continue,
+                    (Some(_), Some(values)) => {
+                        ctx.func_data().dfg_mut().new_value().integer(values[i])
+                    }
+                    (Some(exp), None) => exp.generate(ctx)?,
+                };
+                let ptr = element_ptr(ctx, alloc, i, &lens);
+                let store = ctx.func_data().dfg_mut().new_value().store(value, ptr);
+                ctx.push_inst(store);
+            }
+        }
+        alloc
+    };
+    let symbol = match values {
+        Some(values) if is_const => Symbol::ConstArray(alloc, Rc::new(ConstArray { lens, values })),
+        _ => Symbol::Array(alloc),
+    };
+    ctx.symbols.insert(ident, symbol)
+}
+
+// 按 SysY 的规则把初始化列表展开为 lens 所描述数组的全部元素, 没有指定的元素为 None (即 0)
+//
+// 遇到嵌套的列表时, 它初始化的是当前位置所对齐的最大的子数组 (不包括整个数组本身),
+// 例如 int a[2][3][4] 中, 位置 0 处的列表初始化 a[0], 位置 4 处的列表初始化 a[0][1]
+// 元素过多或者嵌套的列表没有对齐到子数组时, 返回出错的初始值的位置
+pub(crate) fn flatten_init<'a>(
+    list: &'a [InitVal],
+    lens: &[usize],
+    elems: &mut Vec<Option<&'a Exp>>,
+) -> Result<(), Span> {
+    let total: usize = lens.iter().product();
+    let start = elems.len();
+    for init in list {
+        let filled = elems.len() - start;
+        if filled >= total {
+            return Err(init.span());
+        }
+        match init {
+            InitVal::Exp(exp) => elems.push(Some(exp)),
+            InitVal::List(sub_list, span) => {
+                let dim = (1..lens.len())
+                    .find(|&dim| filled.is_multiple_of(lens[dim..].iter().product()))
+                    .ok_or(*span)?;
+                flatten_init(sub_list, &lens[dim..], elems)?;
+            }
+        }
+    }
+    elems.resize(start + total, None);
+    Ok(())
+}
+
+// 由展开后的元素构造全局数组的 aggregate 初始值, 全为 0 时使用 zeroinit
+fn global_aggregate(ctx: &mut Context, values: &[i32], lens: &[usize]) -> Value {
+    if values.iter().all(|&value| value == 0) {
+        return ctx.program.new_value().zero_init(array_type(lens));
+    }
+    match lens.split_first() {
+        None => ctx.program.new_value().integer(values[0]),
+        Some((&len, sub_lens)) => {
+            let mut elems = Vec::new();
+            for chunk in values.chunks(values.len() / len) {
+                elems.push(global_aggregate(ctx, chunk, sub_lens));
+            }
+            ctx.program.new_value().aggregate(elems)
+        }
+    }
+}
+
+// 数组展开后第 index 个元素的地址, 由各维的下标依次 getelemptr 得到
+fn element_ptr(ctx: &mut Context, alloc: Value, mut index: usize, lens: &[usize]) -> Value {
+    let mut ptr = alloc;
+    let mut stride: usize = lens.iter().product();
+    for &len in lens {
+        stride /= len;
+        let sub = ctx
+            .func_data()
+            .dfg_mut()
+            .new_value()
+            .integer((index / stride) as i32);
+        index %= stride;
+        ptr = ctx.func_data().dfg_mut().new_value().get_elem_ptr(ptr, sub);
+        ctx.push_inst(ptr);
+    }
+    ptr
+}
+
+// 计算左值的地址
+// 数组参数保存的是指针, 需要先 load 出来, 再用 getptr 计算第一维的下标
+fn lval_address(ctx: &mut Context, lval: &LVal) -> Result<Value, Error> {
+    let mut ptr = match ctx.symbols.lookup(&lval.ident)? {
+        Symbol::Var(alloc) | Symbol::Array(alloc) | Symbol::ConstArray(alloc, _) => alloc,
+        Symbol::Const(_) => return Err(Error::InvalidSubscript(lval.ident.clone())),
+        Symbol::Func(_) => return Err(Error::NotAVariable(lval.ident.clone())),
+    };
+    for index in &lval.indices {
+        let index = index.generate(ctx)?;
+        ptr = match ctx.pointee_type(ptr).kind() {
+            TypeKind::Array(..) => {
+                let ptr = ctx
+                    .func_data()
+                    .dfg_mut()
+                    .new_value()
+                    .get_elem_ptr(ptr, index);
+                ctx.push_inst(ptr);
+                ptr
+            }
+            TypeKind::Pointer(..) => {
+                let load = ctx.func_data().dfg_mut().new_value().load(ptr);
+                ctx.push_inst(load);
+                let ptr = ctx.func_data().dfg_mut().new_value().get_ptr(load, index);
+                ctx.push_inst(ptr);
+                ptr
+            }
+            _ => return Err(Error::InvalidSubscript(lval.ident.clone())),
+        };
+    }
+    Ok(ptr)
+}
+
+impl GenerateIR for Stmt {
+    type Output = ();
+    fn generate(&self, ctx: &mut Context) -> Result<(), Error> {
+        match self {
+            Stmt::Assign(lval, exp, _) => {
+                let value = exp.generate(ctx)?;
+                if let Symbol::Const(_) | Symbol::ConstArray(..) =
+                    ctx.symbols.lookup(&lval.ident)?
+                {
+                    return Err(Error::AssignToConst(lval.ident.clone()));
+                }
+                let ptr = lval_address(ctx, lval)?;
+                if !ctx.pointee_type(ptr).is_i32() {
+                    return Err(Error::NotAssignable(lval.ident.clone()));
+                }
+                let store = ctx.func_data().dfg_mut().new_value().store(value, ptr);
+                ctx.push_inst(store);
+            }
+            Stmt::Exp(exp, _) => {
+                if let Some(exp) = exp {
+                    exp.generate(ctx)?;
+                }
+            }
+            Stmt::Block(block) => block.generate(ctx)?,
+            Stmt::If {
+                cond, then, else_, ..
+            } => {
+                let cond = cond.generate(ctx)?;
+                let then_bb = ctx.new_bb("then");
+                let end_bb = ctx.new_bb("end");
+                let else_bb = match else_ {
+                    Some(_) => ctx.new_bb("else"),
+                    None => end_bb,
+                };
+                let br = ctx
+                    .func_data()
+                    .dfg_mut()
+                    .new_value()
+                    .branch(cond, then_bb, else_bb);
+                ctx.push_inst(br);
+
+                ctx.enter_bb(then_bb);
+                then.generate(ctx)?;
+                ctx.jump_to(end_bb);
+
+                if let Some(else_) = else_ {
+                    ctx.enter_bb(else_bb);
+                    else_.generate(ctx)?;
+                    ctx.jump_to(end_bb);
+                }
+                ctx.enter_bb(end_bb);
+            }
+            Stmt::While { cond, body, .. } => {
+                let entry_bb = ctx.new_bb("while_entry");
+                let body_bb = ctx.new_bb("while_body");
+                let end_bb = ctx.new_bb("while_end");
+                ctx.jump_to(entry_bb);
+
+                ctx.enter_bb(entry_bb);
+                let cond = cond.generate(ctx)?;
+                let br = ctx
+                    .func_data()
+                    .dfg_mut()
+                    .new_value()
+                    .branch(cond, body_bb, end_bb);
+                ctx.push_inst(br);
+
+                ctx.enter_bb(body_bb);
+                ctx.loops.push((entry_bb, end_bb));
+                body.generate(ctx)?;
+                ctx.loops.pop();
+                ctx.jump_to(entry_bb);
+
+                ctx.enter_bb(end_bb);
+            }
+            Stmt::Break(_) => match ctx.loops.last() {
+                Some(&(_, end_bb)) => ctx.jump_to(end_bb),
+                None => return Err(Error::BreakOutsideLoop),
+            },
+            Stmt::Continue(_) => match ctx.loops.last() {
+                Some(&(entry_bb, _)) => ctx.jump_to(entry_bb),
+                None => return Err(Error::ContinueOutsideLoop),
+            },
+            Stmt::Return(exp, _) => {
+                let value = match exp {
+                    Some(exp) => Some(exp.generate(ctx)?),
+                    None => None,
+                };
+                let ret = ctx.func_data().dfg_mut().new_value().ret(value);
+                ctx.push_inst(ret);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GenerateIR for Exp {
+    type Output = Value;
+    fn generate(&self, ctx: &mut Context) -> Result<Value, Error> {
+        use koopa::ir::BinaryOp as IrOp;
+        Ok(match self {
+            Exp::Number(n, _) => ctx.func_data().dfg_mut().new_value().integer(*n),
+            Exp::LVal(lval) => {
+                // 常量直接替换为它的值
+                if let Symbol::Const(value) = ctx.symbols.lookup(&lval.ident)? {
+                    if !lval.indices.is_empty() {
+                        return Err(Error::InvalidSubscript(lval.ident.clone()));
+                    }
+                    return Ok(ctx.func_data().dfg_mut().new_value().integer(value));
+                }
+                let ptr = lval_address(ctx, lval)?;
+                match ctx.pointee_type(ptr).kind() {
+                    // 数组 (作为函数实参时) 退化为指向第一个元素的指针
+                    TypeKind::Array(..) => {
+                        let zero = ctx.func_data().dfg_mut().new_value().integer(0);
+                        let ptr = ctx
+                            .func_data()
+                            .dfg_mut()
+                            .new_value()
+                            .get_elem_ptr(ptr, zero);
+                        ctx.push_inst(ptr);
+                        ptr
+                    }
+                    _ => {
+                        let load = ctx.func_data().dfg_mut().new_value().load(ptr);
+                        ctx.push_inst(load);
+                        load
+                    }
+                }
+            }
+            Exp::Call(ident, args, _) => {
+                let func = match ctx.symbols.lookup(ident)? {
+                    Symbol::Func(func) => func,
+                    _ => return Err(Error::NotAFunction(ident.clone())),
+                };
+                let params = match ctx.program.func(func).ty().kind() {
+                    TypeKind::Function(params, _) => params.clone(),
+                    _ => unreachable!(),
+                };
+                if args.len() != params.len() {
+                    return Err(Error::ArgCount {
+                        func: ident.clone(),
+                        expected: params.len(),
+                        found: args.len(),
+                    });
+                }
+                // 实参的类型必须与形参相同, 包括数组除第一维外各维的长度, 否则 koopa 会 panic
+                let mut arg_values = Vec::new();
+                for (arg, param) in args.iter().zip(params) {
+                    let value = arg.generate(ctx)?;
+                    let ty = ctx.value_type(value);
+                    if ty != param {
+                        return Err(Error::MismatchedTypes {
+                            expected: param.to_string(),
+                            found: ty.to_string(),
+                        });
+                    }
+                    arg_values.push(value);
+                }
+                let call = ctx.func_data().dfg_mut().new_value().call(func, arg_values);
+                ctx.push_inst(call);
+                call
+            }
+            Exp::UnaryExp(op, exp, _) => {
+                let value = exp.generate(ctx)?;
+                match op {
+                    UnaryOp::Pos => value,
+                    UnaryOp::Neg => {
+                        let zero = ctx.func_data().dfg_mut().new_value().integer(0);
+                        binary(ctx, IrOp::Sub, zero, value)
+                    }
+                    UnaryOp::Not => {
+                        let zero = ctx.func_data().dfg_mut().new_value().integer(0);
+                        binary(ctx, IrOp::Eq, value, zero)
+                    }
+                }
+            }
+            Exp::BinaryExp(lhs, BinaryOp::And, rhs, _) => short_circuit(ctx, lhs, true, rhs)?,
+            Exp::BinaryExp(lhs, BinaryOp::Or, rhs, _) => short_circuit(ctx, lhs, false, rhs)?,
+            Exp::BinaryExp(lhs, op, rhs, _) => {
+                let lhs = lhs.generate(ctx)?;
+                let rhs = rhs.generate(ctx)?;
+                binary(ctx, op.into(), lhs, rhs)
+            }
+        })
+    }
+}
+
+// 短路求值 && (is_and 为 true) 和 ||:
+// 结果先存入一个临时的 alloc, 只有左侧不能决定结果时才跳转到右侧求值
+//
+//   %result = alloc i32
+//   store 0 (|| 时为 1), %result
+//   br lhs, %rhs, %end   (|| 时为 br lhs, %end, %rhs)
+// %rhs:
+//   store (ne rhs, 0), %result
+//   jump %end
+// %end:
+//   load %result
+fn short_circuit(ctx: &mut Context, lhs: &Exp, is_and: bool, rhs: &Exp) -> Result<Value, Error> {
+    use koopa::ir::BinaryOp as IrOp;
+    let result = ctx.func_data().dfg_mut().new_value().alloc(Type::get_i32());
+    ctx.push_inst(result);
+    let init = ctx
+        .func_data()
+        .dfg_mut()
+        .new_value()
+        .integer(!is_and as i32);
+    let store = ctx.func_data().dfg_mut().new_value().store(init, result);
+    ctx.push_inst(store);
+
+    let lhs = lhs.generate(ctx)?;
+    let (rhs_bb, end_bb) = if is_and {
+        (ctx.new_bb("and_rhs"), ctx.new_bb("and_end"))
+    } else {
+        (ctx.new_bb("or_rhs"), ctx.new_bb("or_end"))
+    };
+    let br = if is_and {
+        ctx.func_data()
+            .dfg_mut()
+            .new_value()
+            .branch(lhs, rhs_bb, end_bb)
+    } else {
+        ctx.func_data()
+            .dfg_mut()
+            .new_value()
+            .branch(lhs, end_bb, rhs_bb)
+    };
+    ctx.push_inst(br);
+
+    ctx.enter_bb(rhs_bb);
+    let rhs = rhs.generate(ctx)?;
+    let zero = ctx.func_data().dfg_mut().new_value().integer(0);
+    let rhs = binary(ctx, IrOp::NotEq, rhs, zero);
+    let store = ctx.func_data().dfg_mut().new_value().store(rhs, result);
+    ctx.push_inst(store);
+    ctx.jump_to(end_bb);
+
+    ctx.enter_bb(end_bb);
+    let load = ctx.func_data().dfg_mut().new_value().load(result);
+    ctx.push_inst(load);
+    Ok(load)
+}
+
+// 生成一条二元运算指令并插入当前基本块
+fn binary(ctx: &mut Context, op: koopa::ir::BinaryOp, lhs: Value, rhs: Value) -> Value {
+    let value = ctx.func_data().dfg_mut().new_value().binary(op, lhs, rhs);
+    ctx.push_inst(value);
+    value
+}
+
+impl From<&BinaryOp> for koopa::ir::BinaryOp {
+    fn from(op: &BinaryOp) -> Self {
+        use koopa::ir::BinaryOp as IrOp;
+        match op {
+            BinaryOp::Mul => IrOp::Mul,
+            BinaryOp::Div => IrOp::Div,
+            BinaryOp::Mod => IrOp::Mod,
+            BinaryOp::Add => IrOp::Add,
+            BinaryOp::Sub => IrOp::Sub,
+            BinaryOp::Eq => IrOp::Eq,
+            BinaryOp::Ne => IrOp::NotEq,
+            BinaryOp::Lt => IrOp::Lt,
+            BinaryOp::Gt => IrOp::Gt,
+            BinaryOp::Le => IrOp::Le,
+            BinaryOp::Ge => IrOp::Ge,
+            // && 和 || 需要短路求值, 不对应单条指令
+            BinaryOp::And | BinaryOp::Or => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn num(n: i32) -> InitVal {
+        InitVal::Exp(Exp::Number(n, Span::default()))
+    }
+
+    fn list(items: Vec<InitVal>) -> InitVal {
+        InitVal::List(items, Span::default())
+    }
+
+    // 展开 init 描述的初始化列表, 元素都是整数字面量, 没有指定的元素为 None
+    fn flatten(init: &InitVal, lens: &[usize]) -> Result<Vec<Option<i32>>, Span> {
+        let InitVal::List(list, _) = init else {
+            panic!("not an initializer list");
+        };
+        let mut elems = Vec::new();
+        flatten_init(list, lens, &mut elems)?;
+        Ok(elems
+            .into_iter()
+            .map(|elem| match elem {
+                Some(Exp::Number(n, _)) => Some(*n),
+                None => None,
+                Some(exp) => panic!("unexpected element {:?}", exp),
+            })
+            .collect())
+    }
+
+    #[test]
+    fn flatten_flat_and_nested() {
+        assert_eq!(flatten(&list(vec![]), &[3]), Ok(vec![None; 3]));
+        assert_eq!(
+            flatten(&list(vec![num(1), num(2)]), &[3]),
+            Ok(vec![Some(1), Some(2), None])
+        );
+        // int a[2][2] = {{1, 2}, {3}}
+        let init = list(vec![list(vec![num(1), num(2)]), list(vec![num(3)])]);
+        assert_eq!(
+            flatten(&init, &[2, 2]),
+            Ok(vec![Some(1), Some(2), Some(3), None])
+        );
+        // 不带花括号的元素依次填充, int a[2][2] = {1, 2, 3}
+        let init = list(vec![num(1), num(2), num(3)]);
+        assert_eq!(
+            flatten(&init, &[2, 2]),
+            Ok(vec![Some(1), Some(2), Some(3), None])
+        );
+    }
+
+    #[test]
+    fn flatten_aligned_sub_list() {
+        // int a[2][3][4] = {1, 2, 3, 4, {5}, {6}, {7, 8}}
+        // 位置 4 和 8 处的列表分别初始化 a[0][1] 和 a[0][2], 位置 12 处的列表初始化 a[1]
+        let init = list(vec![
+            num(1),
+            num(2),
+            num(3),
+            num(4),
+            list(vec![num(5)]),
+            list(vec![num(6)]),
+            list(vec![num(7), num(8)]),
+        ]);
+        let mut expected = vec![None; 24];
+        for (i, n) in [
+            (0, 1),
+            (1, 2),
+            (2, 3),
+            (3, 4),
+            (4, 5),
+            (8, 6),
+            (12, 7),
+            (13, 8),
+        ] {
+            expected[i] = Some(n);
+        }
+        assert_eq!(flatten(&init, &[2, 3, 4]), Ok(expected));
+    }
+
+    #[test]
+    fn flatten_errors() {
+        let extra = Span::new(7, 8);
+        // int a[2] = {1, 2, 3}, 出错的位置是多余的元素
+        let init = list(vec![num(1), num(2), InitVal::Exp(Exp::Number(3, extra))]);
+        assert_eq!(flatten(&init, &[2]), Err(extra));
+        // 多余的元素在嵌套的列表中, int a[2][2] = {{1, 2, 3}}
+        let init = list(vec![list(vec![
+            num(1),
+            num(2),
+            InitVal::Exp(Exp::Number(3, extra)),
+        ])]);
+        assert_eq!(flatten(&init, &[2, 2]), Err(extra));
+        // 位置 1 没有对齐到子数组, int a[2][2] = {1, {2}}
+        let misaligned = Span::new(3, 6);
+        let init = list(vec![num(1), InitVal::List(vec![num(2)], misaligned)]);
+        assert_eq!(flatten(&init, &[2, 2]), Err(misaligned));
+        // 一维数组中不能再嵌套列表, int a[2] = {{1}}
+        let init = list(vec![InitVal::List(vec![num(1)], misaligned)]);
+        assert_eq!(flatten(&init, &[2]), Err(misaligned));
+    }
+}
@@ -1,41 +1,79 @@
+pub mod check;
+pub mod diagnostic;
+pub mod eval;
+pub mod irgen;
+pub mod riscv;
+pub mod source_map;
+pub mod symtab;
+pub mod warning;
+
 pub mod ast {
+    use koopa::back::KoopaGenerator;
     use std::fmt;
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    static PC: AtomicUsize = AtomicUsize::new(0);
     #[derive(Debug)]
     pub struct CompUnit {
-        pub func_def: FuncDef,
+        pub items: Vec<GlobalItem>,
+    }
+
+    #[derive(Debug)]
+    pub enum GlobalItem {
+        Decl(Decl),
+        FuncDef(FuncDef),
     }
 
-    impl fmt::Display for CompUnit {
+    impl CompUnit {
+        /// 生成文本形式的 Koopa IR
+        ///
+        /// 每次调用都重新生成 IR, 编号只取决于 AST 本身, 可以重复调用
+        pub fn to_koopa_string(&self) -> Result<String, crate::irgen::Error> {
+            let program = crate::irgen::generate_program(self)?;
+            let mut gen = KoopaGenerator::new(Vec::new());
+            gen.generate_on(&program).expect("writing to a Vec never fails");
+            Ok(String::from_utf8_lossy(&gen.writer()).into_owned())
+        }
+    }
+
+    /// 语法结构在源文件中的字节偏移量范围 [start, end)
+    #[derive(Clone, Copy, Default, PartialEq, Eq)]
+    pub struct Span {
+        pub start: usize,
+        pub end: usize,
+    }
+
+    impl Span {
+        pub fn new(start: usize, end: usize) -> Self {
+            Span { start, end }
+        }
+    }
+
+    // 输出 AST 时只显示偏移量范围, 避免过于冗长
+    impl fmt::Debug for Span {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            write!(f, "{}", self.func_def)
+            write!(f, "{}..{}", self.start, self.end)
         }
     }
+
     #[derive(Debug)]
     pub struct FuncDef {
         pub func_type: FuncType,
         pub ident: String,
+        pub params: Vec<FuncFParam>,
         pub block: Block,
-    }
-
-    impl fmt::Display for FuncDef {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            match self.func_type {
-                FuncType::Int => {
-                    write!(f, "fun @{}(): i32 {{\n{}}}", self.ident, self.block)
-                }
-                FuncType::String => {
-                    write!(f, "fun @{}(): String {{\n{}}}", self.ident, self.block)
-                }
-            }
-        }
+        pub span: Span,
     }
 
     #[derive(Debug)]
     pub enum FuncType {
         Int,
-        String,
+        Void,
+    }
+
+    #[derive(Debug)]
+    pub struct FuncFParam {
+        pub ident: String,
+        // 数组参数除第一维以外的各维长度, 标量参数为 None
+        pub dims: Option<Vec<Exp>>,
+        pub span: Span,
     }
     #[derive(Debug)]
     pub enum UnaryOp {
@@ -60,217 +98,127 @@ pub mod ast {
         Or,
     }
 
-        
     #[derive(Debug)]
     pub enum Exp {
-        Number(i32),
-        UnaryExp(UnaryOp, Box<Exp>),
-        BinaryExp(Box<Exp>, BinaryOp, Box<Exp>),
-    }
-    
-    trait Pc {
-        fn add_pc(&self) -> usize;
-        fn previous_pc(&self) -> usize;
-        fn load_pc(&self) -> usize;
+        Number(i32, Span),
+        UnaryExp(UnaryOp, Box<Exp>, Span),
+        BinaryExp(Box<Exp>, BinaryOp, Box<Exp>, Span),
+        LVal(LVal),
+        Call(String, Vec<Exp>, Span),
     }
 
-    impl Pc for Exp {
-        fn add_pc(&self) -> usize {
-            PC.fetch_add(1, Ordering::Relaxed)
-        }
-
-        fn previous_pc(&self) -> usize {
-            PC.load(Ordering::Relaxed) - 1
-        }
-
-        fn load_pc(&self) -> usize {
-            PC.load(Ordering::Relaxed)
+    impl Exp {
+        pub fn span(&self) -> Span {
+            match self {
+                Exp::Number(_, span)
+                | Exp::UnaryExp(_, _, span)
+                | Exp::BinaryExp(_, _, _, span)
+                | Exp::Call(_, _, span) => *span,
+                Exp::LVal(lval) => lval.span,
+            }
         }
     }
 
-    // 什么时候调用write
-    // 使用rc（多线程不安全）给expr编号
-    impl fmt::Display for Exp {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            match self {
-                Exp::Number(n) => {
-                    // println!("format Number({})", n);
-                    write!(f, "{}", n) //
-                },
-                Exp::UnaryExp(op, exp) => {
-                    // println!("format {:?},",self);
-                    let mut prev_exp = String::new(); // 
-                    let mut prev_stmt = String::new();
-
-                    match &**exp { //当前op的exp
-                        Exp::Number(num) =>{ 
-                            // println!("match number");   
-                            prev_exp = num.to_string()
-                        }, // 当前op的exp，如果是
-                        Exp::UnaryExp(_unaryop, _expression) => {
-                            // println!("format {:?} 's sub exp {:?}",self,exp);
-                            prev_stmt = format!("{}",exp);
-                            // println!("prev_stmt:({})",prev_stmt);
-                            // println!("prev_exp:[{}]",exp.previous_pc().to_string());
-                            prev_exp = format!("%{}", exp.previous_pc().to_string())
-                        },
-                        Exp::BinaryExp(_,_ ,_ )=>{}
-                    }
+    #[derive(Debug)]
+    pub struct LVal {
+        pub ident: String,
+        pub indices: Vec<Exp>,
+        pub span: Span,
+    }
 
-                    match op {
-                        UnaryOp::Pos => {
-                            // print!("<Pos {} Pos>", prev_stmt);
-                            if prev_stmt.is_empty() {
-                                write!(f, "{}", prev_exp)
-                            } else{
-                            write!(f, "{}", prev_stmt)
-                                
-                            }
-                        },
-                        UnaryOp::Neg => {
-                            // print!("<Neg {}%{} = sub 0, {} Neg>\n",prev_stmt, self.load_pc(), prev_exp);
-                            write!(f, "{}%{} = sub 0, {}\n  ",prev_stmt, self.add_pc(), prev_exp)},
-                        UnaryOp::Not => {
-                            // print!("<Not {}%{} = eq {}, 0 Not>\n",prev_stmt, self.load_pc(), prev_exp);
-                            write!(f, "{}%{} = eq {}, 0\n  ",prev_stmt, self.add_pc(), prev_exp)},
-                    }
-                },
-                Exp::BinaryExp(exp1,op ,exp2)=> {
-                    // println!("format {:?},",self);
-                    let mut _prev_exp1 = String::new();
-                    let mut _prev_exp2 = String::new();
-                    let mut prev_stmt1 = String::new();
-                    let mut prev_stmt2 = String::new();
+    #[derive(Debug)]
+    pub struct Block {
+        pub items: Vec<BlockItem>,
+        pub span: Span,
+    }
 
-                    match &**exp1 { //当前op的exp1
-                        Exp::Number(num) =>{
-                            // println!("match number");   
-                            _prev_exp1 = num.to_string()
-                        },
-                        Exp::UnaryExp(_unaryop, _expression) => {
-                            // println!("format {:?} 's sub exp {:?}",self,exp);
-                            prev_stmt1 = format!("{}",exp1);
-                            // println!("prev_stmt:({})",prev_stmt);
-                            // println!("prev_exp:[{}]",exp.previous_pc().to_string());
-                            _prev_exp1 = format!("%{}", exp1.previous_pc().to_string())
-                        },
-                        Exp::BinaryExp(_expa,_op0 ,_expb ) =>{
-                            // println!("format {:?} 's sub exp {:?}",self,exp);
-                            prev_stmt1 = format!("{}",exp1);
-                            // println!("prev_stmt:({})",prev_stmt);
-                            // println!("prev_exp:[{}]",exp.previous_pc().to_string());
-                            _prev_exp1 = format!("%{}", exp1.previous_pc().to_string())
-                        }
-                    }
+    #[derive(Debug)]
+    pub enum BlockItem {
+        Decl(Decl),
+        Stmt(Stmt),
+    }
 
-                    match &**exp2 { //当前op的exp2
-                        Exp::Number(num) =>{ 
-                            // println!("match number");   
-                            _prev_exp2 = num.to_string()
-                        }, // 当前op的exp2，如果是
-                        Exp::UnaryExp(_unaryop, _expression) => {
-                            // println!("format {:?} 's sub exp {:?}",self,exp);
-                            prev_stmt2 = format!("{}",exp2);
-                            // println!("prev_stmt:({})",prev_stmt);
-                            // println!("prev_exp:[{}]",exp.previous_pc().to_string());
-                            _prev_exp2 = format!("%{}", exp2.previous_pc().to_string())
-                        },
-                        Exp::BinaryExp(_expa,_op0 ,_expb )=> {
-                            // println!("format {:?} 's sub exp {:?}",self,exp);
-                            prev_stmt2 = format!("{}",exp2);
-                            // println!("prev_stmt:({})",prev_stmt);
-                            // println!("prev_exp:[{}]",exp.previous_pc().to_string());
-                            _prev_exp2 = format!("%{}", exp2.previous_pc().to_string())
-                        }
-                    }
+    #[derive(Debug)]
+    pub enum Decl {
+        Const(ConstDecl),
+        Var(VarDecl),
+    }
 
-                    match op {
-                        BinaryOp::Mul => {
-                            // print!("<Mul {}%{} = mul {}, {} Mul>\n",prev_stmt1, self.load_pc(), _prev_exp1, _prev_exp2);
-                            write!(f, "{}{}%{} = mul {}, {}\n  ",prev_stmt1,prev_stmt2, self.add_pc(), _prev_exp1, _prev_exp2)
-                        },
-                        BinaryOp::Div => {
-                            // print!("<Div {}%{} = sdiv {}, {} Div>\n",prev_stmt1, self.load_pc(), _prev_exp1, _prev_exp2);
-                            write!(f, "{}{}%{} = div {}, {}\n  ",prev_stmt1,prev_stmt2, self.add_pc(), _prev_exp1, _prev_exp2)
-                        },
-                        BinaryOp::Mod => {
-                            // print!("<Mod {}%{} = srem {}, {} Mod
-                            write!(f, "{}{}%{} = mod {}, {}\n  ",prev_stmt1,prev_stmt2,self.add_pc(), _prev_exp1, _prev_exp2)
-                        },
+    #[derive(Debug)]
+    pub struct ConstDecl {
+        pub defs: Vec<ConstDef>,
+        pub span: Span,
+    }
 
-                        BinaryOp::Add => {
-                            // print!("<Add {}%{} = add {}, {} Add>\n",prev_stmt1, self.load_pc(), _prev_exp1, _prev_exp2);
-                            write!(f, "{}{}%{} = add {}, {}\n  ",prev_stmt1,prev_stmt2,self.add_pc(), _prev_exp1, _prev_exp2)
-                        },
+    #[derive(Debug)]
+    pub struct ConstDef {
+        pub ident: String,
+        pub dims: Vec<Exp>,
+        pub init: InitVal,
+        pub span: Span,
+    }
 
-                        BinaryOp::Sub =>{
-                            // print!("<Sub {}%{} = sub {}, {} Sub>\n",prev_stmt1, self.load_pc(), _prev_exp1, _prev_exp2);
-                            write!(f, "{}{}%{} = sub {}, {}\n  ",prev_stmt1,prev_stmt2, self.add_pc(), _prev_exp1, _prev_exp2)
-                        },
-                        BinaryOp::Eq =>{
-                            // print!("<Eq {}%{} = eq {}, {} Eq>\n",prev_stmt1, self.load_pc(), _prev_exp1, _prev_exp2);
-                            write!(f, "{}{}%{} = eq {}, {}\n  ",prev_stmt1,prev_stmt2, self.add_pc(), _prev_exp1, _prev_exp2)
-                        },
-                        BinaryOp::Le=>{
-                            // print!("<Le {}%{} = sle {}, {} Le>\n",prev_stmt1, self.load_pc(), _prev_exp1, _prev_exp2);
-                            write!(f, "{}{}%{} = le {}, {}\n  ",prev_stmt1,prev_stmt2, self.add_pc(), _prev_exp1, _prev_exp2)
-                        },
-                        BinaryOp::Lt=>{
-                            write!(f, "{}{}%{} = lt {}, {}\n  ",prev_stmt1,prev_stmt2, self.add_pc(), _prev_exp1, _prev_exp2)
-                        },
-                        BinaryOp::Gt =>{
-                            write!(f, "{}{}%{} = gt {}, {}\n  ",prev_stmt1,prev_stmt2, self.add_pc(), _prev_exp1, _prev_exp2)
-                        }
-                        BinaryOp::Ge =>{
-                            write!(f, "{}{}%{} = ge {}, {}\n  ",prev_stmt1,prev_stmt2, self.add_pc(), _prev_exp1, _prev_exp2)
-                        },
+    #[derive(Debug)]
+    pub struct VarDecl {
+        pub defs: Vec<VarDef>,
+        pub span: Span,
+    }
 
-                        BinaryOp::Ne =>{
-                            write!(f, "{}{}%{} = ne {}, {}\n  ",prev_stmt1,prev_stmt2, self.add_pc(), _prev_exp1, _prev_exp2)
-                        },
-                        BinaryOp::And =>{
-                            let pc1 = self.add_pc();
-                            let pc2 = self.add_pc();
-                            write!(f, "{}{}%{} = eq {}, {}\n  %{} = eq {}, {}\n  %{} = eq %{}, %{}\n  ",prev_stmt1,prev_stmt2, pc1, _prev_exp1, 0,pc2, _prev_exp2, 0,self.add_pc(), pc1, pc2)
-                        },
-                        BinaryOp::Or =>{
-                            let pc = self.add_pc();
-                            write!(f, "{}{}%{} = or {}, {}\n  %{} = ne %{}, {}\n  ",prev_stmt1,prev_stmt2, pc, _prev_exp1, _prev_exp2,self.add_pc(), pc, 0)
-                        },
-                        }
-                    }  
-            }
-        }
+    #[derive(Debug)]
+    pub struct VarDef {
+        pub ident: String,
+        pub dims: Vec<Exp>,
+        pub init: Option<InitVal>,
+        pub span: Span,
     }
 
-    
-    // impl fmt::Display for MulExp {
-        
-    // }
     #[derive(Debug)]
-    pub struct Block {
-        pub stmt: Stmt,
+    pub enum InitVal {
+        Exp(Exp),
+        List(Vec<InitVal>, Span),
     }
 
-    impl fmt::Display for Block {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            write!(f, "%entry:\n  {}", self.stmt)
+    impl InitVal {
+        pub fn span(&self) -> Span {
+            match self {
+                InitVal::Exp(exp) => exp.span(),
+                InitVal::List(_, span) => *span,
+            }
         }
     }
+
     #[derive(Debug)]
-    pub struct Stmt {
-        pub exp: Exp,
+    pub enum Stmt {
+        Assign(LVal, Exp, Span),
+        Exp(Option<Exp>, Span),
+        Block(Block),
+        If {
+            cond: Exp,
+            then: Box<Stmt>,
+            else_: Option<Box<Stmt>>,
+            span: Span,
+        },
+        While {
+            cond: Exp,
+            body: Box<Stmt>,
+            span: Span,
+        },
+        Break(Span),
+        Continue(Span),
+        Return(Option<Exp>, Span),
     }
-    impl fmt::Display for Stmt {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            // print!("{}ret %{}\n", self.exp, self.exp.load_pc());
-            let prev_stmt = format!("{}",self.exp);
-            if self.exp.load_pc() == 0 {
-                write!(f, "ret {}\n", prev_stmt)
-                
-            } else {
-                write!(f, "{}ret %{}\n", prev_stmt, self.exp.previous_pc())
 
+    impl Stmt {
+        pub fn span(&self) -> Span {
+            match self {
+                Stmt::Assign(_, _, span)
+                | Stmt::Exp(_, span)
+                | Stmt::If { span, .. }
+                | Stmt::While { span, .. }
+                | Stmt::Break(span)
+                | Stmt::Continue(span)
+                | Stmt::Return(_, span) => *span,
+                Stmt::Block(block) => block.span,
             }
         }
     }
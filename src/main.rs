@@ -1,260 +1,305 @@
-use koopa::ir::FunctionData;
-use koopa::ir::Value;
-use lalrpop_util::lalrpop_mod;
-use std::collections::HashMap;
+use cli::{Command, Emit, Options};
+use compiler::diagnostic::{self, Diagnostic, Level};
+use compiler::source_map::SourceMap;
+use koopa::back::KoopaGenerator;
+use koopa::front::Driver;
+use koopa::ir::Program;
+use lalrpop_util::{lalrpop_mod, ParseError};
 use std::env::args;
-use std::fs::read_to_string;
-use std::fs::File;
-use std::io::{BufWriter, Result, Write};
-use std::string::ToString;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::exit;
 
+mod cli;
 
 // 引用 lalrpop 生成的解析器
 // 因为我们刚刚创建了 sysy.lalrpop, 所以模块名是 sysy
-lalrpop_mod!(sysy);
+lalrpop_mod!(#[allow(clippy::all)] sysy);
 
-pub struct InstRet{
-    pub reg:String,
-    pub valuekind:String, // 新建String表示类型名
-}
+// 最多输出的错误和警告个数, 避免一个错误引起的大量后续错误刷屏
+const MAX_DIAGNOSTICS: usize = 20;
 
-#[derive(PartialEq, Eq)]
-pub enum ParentType {
-    Binary,
-    Return,
-    None
-}
+fn main() {
+    // 解析命令行参数, 用法错误时退出码为 2
+    let options = match cli::parse_args(args().skip(1)) {
+        Ok(Command::Compile(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Ok(Command::Version) => {
+            println!("compiler {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            eprintln!("Run `compiler --help` for usage.");
+            exit(2);
+        }
+    };
 
-// 根据内存形式 Koopa IR 生成汇编
-trait GenerateAsm {
-    fn generate(&self, _result: &mut String) {}
-    fn generate_inst(&self, _result: &mut String, _env: &FunctionData,_regs:&Vec<&str>,_reg_index:&mut usize,_inst_reg:&mut HashMap<Value,String>,_parent_type:ParentType) -> InstRet{
-        InstRet{reg:"".to_string(),valuekind:"".to_string()}
-    } 
+    let source = read_input(options.input.as_deref()).unwrap_or_else(|err| fail(&err));
+    let map = SourceMap::new(options.input.as_deref().unwrap_or("<stdin>"), source);
+    // 所有阶段都成功后才写入输出, 避免留下不完整的输出文件
+    match compile(&options, map.source()) {
+        Ok((output, warnings)) => {
+            report(&warnings, &map);
+            write_output(options.output.as_deref(), &output).unwrap_or_else(|err| fail(&err))
+        }
+        Err(diags) => {
+            report(&diags, &map);
+            exit(1);
+        }
+    }
 }
 
-impl GenerateAsm for koopa::ir::Program {
-    fn generate(&self, result: &mut String) {
-        result.push_str("  .text\n");
-        result.push_str("  .global main\n");
-        // program遍历函数列表
-        for &func in self.func_layout() {
-            // 访问函数
-            self.func(func).generate(result);
-        }
+// 输出诊断信息, 最多输出 MAX_DIAGNOSTICS 条
+fn report(diags: &[Diagnostic], map: &SourceMap) {
+    for diag in diags.iter().take(MAX_DIAGNOSTICS) {
+        eprint!("{}", diag.render(map));
+    }
+    if diags.len() > MAX_DIAGNOSTICS {
+        eprintln!(
+            "note: too many diagnostics, {} more not shown",
+            diags.len() - MAX_DIAGNOSTICS
+        );
     }
 }
 
-impl GenerateAsm for koopa::ir::FunctionData {
-    fn generate(&self, result: &mut String) {
-        result.push_str(&self.name()[1..]);
-        result.push_str(":\n");
+fn fail(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    exit(1);
+}
+
+// 按选项编译输入, 成功时返回各个阶段的输出和警告, 失败时返回所有的错误和警告
+fn compile(options: &Options, source: &str) -> Result<(String, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let mut output = String::new();
+    let mut diags = Vec::new();
 
-        let regs = vec!["t0","t1","t2","t3","t4","t5","t6", "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"];
-        let mut reg_index = 0;
-        let mut inst_reg:HashMap<Value,String> = HashMap::new();
+    let program = if options.koopa_in {
+        // 直接读入文本形式的 Koopa IR, 跳过 SysY 前端
+        parse_koopa(options.input.as_deref(), source).map_err(|diag| vec![diag])?
+    } else {
+        // 调用 lalrpop 生成的 parser 解析输入文件
+        // 可以恢复的语法错误记录在 errors 中, 无法恢复时 parse 返回最后一个错误
+        let mut errors = Vec::new();
+        let result = sysy::CompUnitParser::new().parse(&mut errors, source);
+        // 动作中报告的错误 (如整数字面量超出范围) 不会跳过任何代码
+        let recovered = errors
+            .iter()
+            .any(|err| !matches!(err.error, ParseError::User { .. }));
+        diags.extend(
+            errors
+                .into_iter()
+                .map(|err| diagnostic::from_parse_error(err.error)),
+        );
+        let ast = result.map_err(|err| {
+            diags.push(diagnostic::from_parse_error(err));
+            std::mem::take(&mut diags)
+        })?;
+        if options.emit.contains(&Emit::Ast) {
+            output.push_str(&format!("{:#?}\n", ast));
+        }
+        // 语义检查, 与语法错误一起报告
+        diags.extend(compiler::check::check(&ast, &options.warnings, recovered));
+        if diags.iter().any(|diag| diag.level == Level::Error) {
+            return Err(diags);
+        }
+        // 直接由 AST 构建内存形式的 Koopa IR
+        match compiler::irgen::generate_program(&ast) {
+            Ok(program) => program,
+            Err(err) => {
+                diags.push(err.into());
+                return Err(diags);
+            }
+        }
+    };
 
-        // 遍历基本块列表
-        for (&_bb, node) in self.layout().bbs() {
-            // 遍历指令列表
-            for &inst in node.insts().keys() {
-                // 处理指令
-                inst.generate_inst(result, self,&regs,&mut reg_index,&mut inst_reg,ParentType::None);
+    if options.emit.contains(&Emit::Koopa) {
+        // 文本形式 IR
+        let mut koopa = Vec::new();
+        KoopaGenerator::new(&mut koopa)
+            .generate_on(&program)
+            .map_err(|err| vec![Diagnostic::error(err.to_string())])?;
+        output.push_str(&String::from_utf8_lossy(&koopa));
+    }
+    if options.emit.contains(&Emit::Riscv) {
+        // RISC-V 汇编
+        match compiler::riscv::generate_asm(&program, options.opt_level) {
+            Ok(asm) => output.push_str(&asm),
+            Err(err) => {
+                diags.push(err.into());
+                return Err(diags);
             }
         }
     }
+    Ok((output, diags))
 }
 
-impl GenerateAsm for koopa::ir::entities::Value {
-    fn generate_inst(&self, result: &mut String, env: &FunctionData,regs:&Vec<&str>,reg_index:&mut usize,inst_reg:&mut HashMap<Value,String>,parent_type:ParentType) -> InstRet{
-        use koopa::ir::ValueKind;
-        use koopa::ir::BinaryOp::*;
-        let value_data = env.dfg().value(*self);
-
-        match value_data.kind() {
-            ValueKind::Integer(int) => {
-
-                // 1. 父类型是二元运算，
-                //     1.1 val非0，rd为临时寄存器，添加指令li reg, val
-                //     1.2 val为0，rd为x0，不添加指令
-                // 2. 父类型时return，rd为a0/a1，添加指令li rd，val
-                let val = int.value();
-                let mut rd = "";
-                match parent_type {
-                    ParentType::Binary => {
-                        if val != 0 {
-                            rd = regs[*reg_index];
-                            *reg_index  += 1;
-                            let str = "  li    ".to_string() + rd+ ", " + val.to_string().as_str() + "\n";
-                    result.push_str(&str);
-                        } else {
-                            rd = "x0";
-                        }
-                    },
-                    ParentType::Return => {
-                        rd = "a0";
-                        let str = "  li    ".to_string() + rd+ ", " + val.to_string().as_str() + "\n";
-                        result.push_str(&str);
-                    },
-                    _ => {}
-                };
+// 读取输入文件, None 表示标准输入
+fn read_input(input: Option<&str>) -> Result<String, String> {
+    match input {
+        Some(path) => {
+            fs::read_to_string(path).map_err(|err| format!("cannot read `{}`: {}", path, err))
+        }
+        None => {
+            let mut source = String::new();
+            io::stdin()
+                .read_to_string(&mut source)
+                .map_err(|err| format!("cannot read stdin: {}", err))?;
+            Ok(source)
+        }
+    }
+}
 
-                    return InstRet{reg:rd.to_string(),valuekind:"Integer".to_string()};
-                } ,
-            ValueKind::Return(ret) => {
-                match ret.value() {
-                    Some(value) => {
-                        let inst_ret =value.generate_inst(result, env,regs,reg_index,inst_reg,ParentType::Return);
-                        if inst_ret.valuekind == "Binary" {
-                            let str = "  mv    a0, ".to_string() + inst_ret.reg.as_str() + "\n";
-                            result.push_str(&str);
-                        }
-                    }
-                    None => {}
-                }
-                result.push_str("  ret\n");
-                return InstRet{reg:"".to_string(),valuekind:"Return".to_string()};
-            }
-            ValueKind::Binary(binaryop)=>{
-                // 父类型时表达式时不添加指令
-                if parent_type != ParentType::None {
-                    return InstRet{reg:inst_reg[&self].to_string(),valuekind:"Binary".to_string()} ;
-                }
-                let lhs_ret = binaryop.lhs().generate_inst(result, env,regs,reg_index,inst_reg,ParentType::Binary);
-                let rhs_ret= binaryop.rhs().generate_inst(result, env,regs,reg_index,inst_reg,ParentType::Binary);
+// 写入输出文件, None 表示标准输出
+fn write_output(output: Option<&str>, content: &str) -> Result<(), String> {
+    match output {
+        Some(path) => {
+            fs::write(path, content).map_err(|err| format!("cannot write `{}`: {}", path, err))
+        }
+        None => io::stdout()
+            .write_all(content.as_bytes())
+            .map_err(|err| format!("cannot write stdout: {}", err)),
+    }
+}
 
-                // 当类型为integer且非零时，可以复用reg
-                let mut _rd_reg=String::new();
-                if &lhs_ret.valuekind =="Integer" &&lhs_ret.reg != "x0" {
-                    _rd_reg = lhs_ret.reg.clone();
-                } else if &rhs_ret.valuekind =="Integer" &&rhs_ret.reg != "x0" {
-                    _rd_reg = rhs_ret.reg.clone();
-                } else {
-                    _rd_reg = regs[*reg_index].to_string();
-                    *reg_index  += 1;
-                }
+// 读入文本形式的 Koopa IR, 其中的错误由 koopa 的前端输出
+fn parse_koopa(input: Option<&str>, source: &str) -> Result<Program, Diagnostic> {
+    // 从文件读入时 koopa 的前端可以在错误信息中显示源代码
+    let program = match input {
+        Some(path) => Driver::from_path(path)
+            .map_err(|err| Diagnostic::error(format!("cannot read `{}`: {}", path, err)))?
+            .generate_program(),
+        None => Driver::from(source).generate_program(),
+    };
+    program.map_err(|_| Diagnostic::error("invalid Koopa IR"))
+}
 
-                inst_reg.insert(*self, _rd_reg.clone());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compiler::warning::{Warning, Warnings};
 
-                match binaryop.op() {
-                    Eq =>{
-                        let str = "  xor   ".to_string() + _rd_reg.as_str() + ", " + lhs_ret.reg.as_str() + ", " + rhs_ret.reg.as_str() + "\n";
-                        result.push_str(&str);
-                        let str = "  seqz  ".to_string() + _rd_reg.as_str() + ", " + _rd_reg.as_str() + "\n";
-                        result.push_str(&str);
-                            
-                        InstRet{reg:_rd_reg,valuekind:"Binary".to_string()}
-                    },
-                    Sub=>{
-                        let str = "  sub   ".to_string() + _rd_reg.as_str() + ", " + lhs_ret.reg.as_str() + ", " + rhs_ret.reg.as_str() + "\n";
-                        result.push_str(&str);
-                        InstRet{reg:_rd_reg,valuekind:"Binary".to_string()}
-                    },
-                    Mul=>{
-                        let str = "  mul   ".to_string() + _rd_reg.as_str() + ", " + lhs_ret.reg.as_str() + ", " + rhs_ret.reg.as_str() + "\n";
-                        result.push_str(&str);
-                        InstRet{reg:_rd_reg,valuekind:"Binary".to_string()}
-                    },
-                    Add=>{
-                        let str = "  add   ".to_string() + _rd_reg.as_str() + ", " + lhs_ret.reg.as_str() + ", " + rhs_ret.reg.as_str() + "\n";
-                        result.push_str(&str);
-                        InstRet{reg:_rd_reg,valuekind:"Binary".to_string()}
-                    },
-                    Le => {
-                        let str = "  sgt   ".to_string() + _rd_reg.as_str() + ", " + lhs_ret.reg.as_str() + ", " + rhs_ret.reg.as_str() + "\n" + "  seqz  " + _rd_reg.as_str() + ", " + _rd_reg.as_str() + "\n";
-                        result.push_str(&str);
-                        InstRet{reg:_rd_reg,valuekind:"Binary".to_string()}
-                    },
-                    Div =>{
-                        let str = "  div   ".to_string() + _rd_reg.as_str() + ", " + lhs_ret.reg.as_str() + ", " + rhs_ret.reg.as_str() + "\n";
-                        result.push_str(&str);
-                        InstRet{reg:_rd_reg,valuekind:"Binary".to_string()}
-                    },
-                    Mod =>{
-                        let str = "  rem   ".to_string() + _rd_reg.as_str() + ", " + lhs_ret.reg.as_str() + ", " + rhs_ret.reg.as_str() + "\n";
-                        result.push_str(&str);
-                        InstRet{reg:_rd_reg,valuekind:"Binary".to_string()}
-                    },
-                    Lt =>{
-                        let str = "  slt   ".to_string() + _rd_reg.as_str() + ", " + lhs_ret.reg.as_str() + ", " + rhs_ret.reg.as_str() + "\n";
-                        result.push_str(&str);
-                        InstRet{reg:_rd_reg,valuekind:"Binary".to_string()}
-                    },
-                    Gt =>{
-                        let str = "  sgt   ".to_string() + _rd_reg.as_str() + ", " + lhs_ret.reg.as_str() + ", " + rhs_ret.reg.as_str() + "\n";
-                        result.push_str(&str);
-                        InstRet{reg:_rd_reg,valuekind:"Binary".to_string()}
-                    },
-                    Ge => {
-                        let str = "  slt   ".to_string() + _rd_reg.as_str() + ", " + lhs_ret.reg.as_str() + ", " + rhs_ret.reg.as_str() + "\n" + "  seqz  " + _rd_reg.as_str() + ", " + _rd_reg.as_str() + "\n";
-                        result.push_str(&str);
-                        InstRet{reg:_rd_reg,valuekind:"Binary".to_string()}
-                    },
-                    And =>{
-                        let str = "  snez   ".to_string() + lhs_ret.reg.as_str() +", " + lhs_ret.reg.as_str()+"\n" + "  snez   " + rhs_ret.reg.as_str() +", " + rhs_ret.reg.as_str()+"\n"+ "  and   " + _rd_reg.as_str() + ", " + lhs_ret.reg.as_str() + ", " + rhs_ret.reg.as_str() + "\n";
-                        result.push_str(&str);
-                        InstRet{reg:_rd_reg,valuekind:"Binary".to_string()}
-                    },
-                    Or =>{
-                        let str = "  or    ".to_string() + _rd_reg.as_str() + ", " + lhs_ret.reg.as_str() + ", " + rhs_ret.reg.as_str() + "\n" + "  snez  " + _rd_reg.as_str() + ", " + _rd_reg.as_str() + "\n";
-                        result.push_str(&str);
-                        InstRet{reg:_rd_reg,valuekind:"Binary".to_string()}
-                    },
-                    NotEq => {
-                        let str = "  xor   ".to_string() + _rd_reg.as_str() + ", " + lhs_ret.reg.as_str() + ", " + rhs_ret.reg.as_str() + "\n";
-                        result.push_str(&str);
-                        let str = "  snez  ".to_string() + _rd_reg.as_str() + ", " + _rd_reg.as_str() + "\n";
-                        result.push_str(&str);
-                            
-                        InstRet{reg:_rd_reg,valuekind:"Binary".to_string()}
-                    },
-                    _ =>{unreachable!()}
+    // 把源程序翻译为文本形式的 Koopa IR, 按基本块切分为 (基本块名, 其中的指令)
+    fn koopa_blocks(source: &str) -> Vec<(String, String)> {
+        let mut errors = Vec::new();
+        let ast = sysy::CompUnitParser::new()
+            .parse(&mut errors, source)
+            .unwrap();
+        assert!(errors.is_empty());
+        let mut blocks: Vec<(String, String)> = Vec::new();
+        for line in ast.to_koopa_string().unwrap().lines() {
+            match line.strip_suffix(':') {
+                Some(label) if label.starts_with('%') => {
+                    blocks.push((label.to_string(), String::new()))
                 }
+                _ => {
+                    if let Some((_, insts)) = blocks.last_mut() {
+                        insts.push_str(line);
+                        insts.push('\n');
+                    }
                 }
-            _ => unreachable!(),
+            }
         }
-            // 其他种类暂时遇不到
+        blocks
     }
-}
 
-fn main() -> Result<()> {
-    // 解析命令行参数
-    let mut args = args();
-    args.next();
-    let mode = args.next().unwrap();
-    // print!("{}", mode);
-    let input = args.next().unwrap();
-    args.next();
-    let output = args.next().unwrap();
+    // 右侧有副作用的调用只能出现在右侧的基本块中, 否则短路时也会被执行
+    #[test]
+    fn short_circuit_rhs_call() {
+        let source = "
+            int n = 0;
+            int f() { n = n + 1; return 1; }
+            int main() {
+              int a = getint();
+              if (a && f()) a = 2;
+              if (a || f()) a = 3;
+              return a;
+            }";
+        let blocks = koopa_blocks(source);
+        let labels: Vec<&str> = blocks
+            .iter()
+            .filter(|(_, insts)| insts.contains("call @f()"))
+            .map(|(label, _)| label.as_str())
+            .collect();
+        assert_eq!(labels.len(), 2, "{:?}", labels);
+        assert!(labels[0].starts_with("%and_rhs_"), "{:?}", labels);
+        assert!(labels[1].starts_with("%or_rhs_"), "{:?}", labels);
+    }
 
-    // 读取输入文件
-    let input = read_to_string(input)?;
+    // 用给定的警告选项检查源程序, 返回 (级别, 信息)
+    fn check_warnings(source: &str, warnings: &Warnings) -> Vec<(Level, String)> {
+        let mut errors = Vec::new();
+        let ast = sysy::CompUnitParser::new()
+            .parse(&mut errors, source)
+            .unwrap();
+        compiler::check::check(&ast, warnings, false)
+            .into_iter()
+            .map(|diag| (diag.level, diag.message))
+            .collect()
+    }
 
-    // 调用 lalrpop 生成的 parser 解析输入文件
-    let ast = sysy::CompUnitParser::new().parse(&input).unwrap();
+    #[test]
+    fn warning_options() {
+        let source = "
+            int f() { int x = 1; }
+            int main() { return 1 / 0; }";
+        let unused = "unused variable `x`";
+        let missing = "control reaches end of non-void function `f`";
+        let div = "division by zero";
 
-    // parse input file
-    println!("{:?}", ast);
-    println!("{}", ast);
+        let mut warnings = Warnings::default();
+        assert_eq!(
+            check_warnings(source, &warnings),
+            [
+                (Level::Warning, format!("{} [-Wunused-variable]", unused)),
+                (Level::Warning, format!("{} [-Wmissing-return]", missing)),
+                (Level::Warning, format!("{} [-Wdivision-by-zero]", div)),
+            ]
+        );
 
-    let driver = koopa::front::Driver::from(ast.to_string());
-    let program = driver.generate_program().unwrap();
-    // 数据和layout是分离表示的
-    let mut program_str = String::new();
-    program.generate(&mut program_str);
-    let write_file = File::create(output).unwrap();
-    let mut writer = BufWriter::new(&write_file);
+        // -Wno-unused-variable -Werror=division-by-zero
+        warnings.disable(Warning::UnusedVariable);
+        warnings.enable_error(Warning::DivisionByZero);
+        assert_eq!(
+            check_warnings(source, &warnings),
+            [
+                (Level::Warning, format!("{} [-Wmissing-return]", missing)),
+                (Level::Error, format!("{} [-Werror=division-by-zero]", div)),
+            ]
+        );
 
-    match mode.as_str() {
-        "-koopa" => {
-            // 文本形式IR，文件output
-            write!(&mut writer, "{}", ast)
-        }
-        "-riscv" => {
-            // RISC-V汇编，文件output
-            println!("{}",program_str);
-            write!(&mut writer, "{}", program_str)
+        // -Werror 把其余开启的警告都当作错误
+        warnings.werror = true;
+        assert_eq!(
+            check_warnings(source, &warnings),
+            [
+                (
+                    Level::Error,
+                    format!("{} [-Werror=missing-return]", missing)
+                ),
+                (Level::Error, format!("{} [-Werror=division-by-zero]", div)),
+            ]
+        );
+    }
+
+    // 没有经过语义检查时, 实参数组的长度与形参不同也要报错, 不能让 koopa panic
+    #[test]
+    fn irgen_mismatched_array_arg() {
+        let source = "
+            int f(int a[][3]) { return a[1][2]; }
+            int main() { int b[2][4]; return f(b); }";
+        let mut errors = Vec::new();
+        let ast = sysy::CompUnitParser::new()
+            .parse(&mut errors, source)
+            .unwrap();
+        match compiler::irgen::generate_program(&ast) {
+            Err(err) => assert_eq!(
+                err.to_string(),
+                "mismatched types: expected `*[i32, 3]`, found `*[i32, 4]`"
+            ),
+            Ok(_) => panic!("expected an error"),
         }
-        _ => unreachable!(),
     }
 }
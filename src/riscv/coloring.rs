@@ -0,0 +1,554 @@
+// 图着色寄存器分配: 迭代寄存器合并 (Iterated Register Coalescing, George & Appel)
+//
+// 由活跃变量分析建立冲突图, 在简化 (simplify)、合并 (coalesce)、冻结 (freeze)、
+// 选择溢出 (select spill) 之间循环, 最后按出栈顺序着色
+// 溢出的值在每次使用时由 t0/t1 从栈上加载, 不需要改写程序再重新分配
+//
+// 冲突图的前 REGS.len() 个结点是预着色的物理寄存器, 之后的结点对应需要分配寄存器的值
+// 传送指令来自调用约定: 入口处参数从 a0-a7 传入, 调用前把实参放到 a0-a7,
+// 调用后从 a0 取得返回值, 以及 ret 时把返回值放到 a0
+// call 会破坏所有的 caller-saved 寄存器, 所以跨过调用的值只能分配到 callee-saved 寄存器
+use super::liveness::{block_args, needs_reg, operands, reg_params, successors, Liveness};
+use super::{is_callee_saved, Allocation, ARG_REGS, REGS};
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+pub fn allocate(func: &FunctionData, liveness: &Liveness) -> Allocation {
+    let mut graph = Graph::new(func);
+    graph.build(func, liveness);
+    graph.make_worklist();
+    loop {
+        if let Some(&node) = graph.simplify_worklist.first() {
+            graph.simplify(node);
+        } else if let Some(&mv) = graph.worklist_moves.first() {
+            graph.coalesce(mv);
+        } else if let Some(&node) = graph.freeze_worklist.first() {
+            graph.freeze(node);
+        } else if !graph.spill_worklist.is_empty() {
+            graph.select_spill();
+        } else {
+            break;
+        }
+    }
+    graph.assign_colors();
+
+    let mut allocation = Allocation::default();
+    for (i, &value) in graph.values.iter().enumerate() {
+        match graph.color[REGS.len() + i] {
+            Some(color) => {
+                allocation.regs.insert(value, REGS[color]);
+            }
+            None => allocation.spilled.push(value),
+        }
+    }
+    allocation
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeState {
+    Precolored,
+    Initial,
+    Simplify,
+    Freeze,
+    Spill,
+    Spilled,
+    Coalesced,
+    Colored,
+    OnStack,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MoveState {
+    Coalesced,
+    Constrained,
+    Frozen,
+    Worklist,
+    Active,
+}
+
+#[derive(Debug)]
+struct Graph {
+    // 结点对应的值 (不包括预着色结点)
+    values: Vec<Value>,
+    index: HashMap<Value, usize>,
+    state: Vec<NodeState>,
+    adj_set: HashSet<(usize, usize)>,
+    adj_list: Vec<Vec<usize>>,
+    degree: Vec<usize>,
+    // 溢出代价: 每次定义和使用按所在循环的深度加权
+    cost: Vec<f64>,
+    alias: Vec<usize>,
+    color: Vec<Option<usize>>,
+    // 传送指令 (目标, 源) 及其状态, 以及与每个结点相关的传送指令
+    moves: Vec<(usize, usize)>,
+    move_state: Vec<MoveState>,
+    move_list: Vec<Vec<usize>>,
+    simplify_worklist: BTreeSet<usize>,
+    freeze_worklist: BTreeSet<usize>,
+    spill_worklist: BTreeSet<usize>,
+    worklist_moves: BTreeSet<usize>,
+    select_stack: Vec<usize>,
+}
+
+// 可用的颜色数
+const K: usize = REGS.len();
+
+// 物理寄存器对应的预着色结点
+fn reg(name: &str) -> usize {
+    REGS.iter().position(|&reg| reg == name).unwrap()
+}
+
+impl Graph {
+    fn new(func: &FunctionData) -> Self {
+        let mut values = reg_params(func);
+        for (&bb, node) in func.layout().bbs() {
+            values.extend(func.dfg().bb(bb).params());
+            for &inst in node.insts().keys() {
+                if needs_reg(func, inst) {
+                    values.push(inst);
+                }
+            }
+        }
+        let index = values
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| (value, K + i))
+            .collect();
+        let n = K + values.len();
+        let mut state = vec![NodeState::Initial; n];
+        let mut degree = vec![0; n];
+        let mut color = vec![None; n];
+        for reg in 0..K {
+            state[reg] = NodeState::Precolored;
+            degree[reg] = usize::MAX / 2;
+            color[reg] = Some(reg);
+        }
+        Graph {
+            values,
+            index,
+            state,
+            adj_set: HashSet::new(),
+            adj_list: vec![Vec::new(); n],
+            degree,
+            cost: vec![0.0; n],
+            alias: (0..n).collect(),
+            color,
+            moves: Vec::new(),
+            move_state: Vec::new(),
+            move_list: vec![Vec::new(); n],
+            simplify_worklist: BTreeSet::new(),
+            freeze_worklist: BTreeSet::new(),
+            spill_worklist: BTreeSet::new(),
+            worklist_moves: BTreeSet::new(),
+            select_stack: Vec::new(),
+        }
+    }
+
+    fn is_precolored(&self, node: usize) -> bool {
+        node < K
+    }
+
+    // 由各基本块出口处的活跃集合, 逆序扫描指令建立冲突图
+    fn build(&mut self, func: &FunctionData, liveness: &Liveness) {
+        let depth = loop_depth(func);
+        for (&bb, node) in func.layout().bbs() {
+            let weight = 10f64.powi(depth[&bb] as i32);
+            let mut live: HashSet<usize> = liveness.live_out[&bb]
+                .iter()
+                .map(|value| self.index[value])
+                .collect();
+            let insts: Vec<Value> = node.insts().keys().copied().collect();
+            for &inst in insts.iter().rev() {
+                let uses: Vec<usize> = operands(func, inst)
+                    .iter()
+                    .map(|value| self.index[value])
+                    .collect();
+                for &node in &uses {
+                    self.cost[node] += weight;
+                }
+                match func.dfg().value(inst).kind() {
+                    ValueKind::Return(ret) => {
+                        // ret 相当于把返回值传送到 a0
+                        if let Some(value) = ret.value().filter(|&v| needs_reg(func, v)) {
+                            self.move_to(&mut live, reg("a0"), self.index[&value]);
+                        }
+                    }
+                    ValueKind::Call(call) => {
+                        // 从 a0 取得返回值
+                        if needs_reg(func, inst) {
+                            let value = self.index[&inst];
+                            self.cost[value] += weight;
+                            self.move_to(&mut live, value, reg("a0"));
+                        }
+                        // call 破坏所有的 caller-saved 寄存器, 并使用传递参数的寄存器
+                        for (color, &name) in REGS.iter().enumerate() {
+                            if !is_callee_saved(name) {
+                                self.define(&mut live, color);
+                            }
+                        }
+                        let args = &call.args()[..call.args().len().min(ARG_REGS.len())];
+                        for name in &ARG_REGS[..args.len()] {
+                            live.insert(reg(name));
+                        }
+                        // 调用前把实参传送到 a0-a7, 通过栈传递的实参直接使用
+                        for (i, &arg) in args.iter().enumerate().rev() {
+                            if needs_reg(func, arg) {
+                                self.move_to(&mut live, reg(ARG_REGS[i]), self.index[&arg]);
+                            } else {
+                                self.define(&mut live, reg(ARG_REGS[i]));
+                            }
+                        }
+                    }
+                    ValueKind::Branch(_) | ValueKind::Jump(_) => {
+                        // 跳转时把实参传送到目标基本块的参数, 尽量合并以省去传送
+                        for (param, arg) in block_args(func, inst) {
+                            if needs_reg(func, arg) {
+                                self.add_move(self.index[&param], self.index[&arg]);
+                            }
+                        }
+                    }
+                    _ if needs_reg(func, inst) => {
+                        let value = self.index[&inst];
+                        self.cost[value] += weight;
+                        self.define(&mut live, value);
+                    }
+                    _ => {}
+                }
+                live.extend(uses);
+            }
+            // 基本块参数在开头定义
+            for &param in func.dfg().bb(bb).params() {
+                let value = self.index[&param];
+                self.cost[value] += weight;
+                self.define(&mut live, value);
+            }
+            // 入口处参数从 a0-a7 传入
+            if Some(bb) == func.layout().entry_bb() {
+                for (i, param) in reg_params(func).into_iter().enumerate().rev() {
+                    let value = self.index[&param];
+                    self.cost[value] += weight;
+                    self.move_to(&mut live, value, reg(ARG_REGS[i]));
+                }
+            }
+        }
+    }
+
+    // 定义结点: 与当前活跃的所有结点冲突
+    fn define(&mut self, live: &mut HashSet<usize>, def: usize) {
+        for &node in live.iter() {
+            self.add_edge(node, def);
+        }
+        live.remove(&def);
+    }
+
+    // 传送指令 dst <- src: dst 不与 src 冲突, 之前 src 活跃
+    fn move_to(&mut self, live: &mut HashSet<usize>, dst: usize, src: usize) {
+        live.remove(&src);
+        self.add_move(dst, src);
+        self.define(live, dst);
+        live.insert(src);
+    }
+
+    fn add_move(&mut self, dst: usize, src: usize) {
+        let mv = self.moves.len();
+        self.moves.push((dst, src));
+        self.move_state.push(MoveState::Worklist);
+        self.worklist_moves.insert(mv);
+        self.move_list[dst].push(mv);
+        self.move_list[src].push(mv);
+    }
+
+    fn add_edge(&mut self, u: usize, v: usize) {
+        if u == v || self.adj_set.contains(&(u, v)) {
+            return;
+        }
+        self.adj_set.insert((u, v));
+        self.adj_set.insert((v, u));
+        for (a, b) in [(u, v), (v, u)] {
+            if !self.is_precolored(a) {
+                self.adj_list[a].push(b);
+                self.degree[a] += 1;
+            }
+        }
+    }
+
+    fn make_worklist(&mut self) {
+        for node in K..self.state.len() {
+            if self.degree[node] >= K {
+                self.state[node] = NodeState::Spill;
+                self.spill_worklist.insert(node);
+            } else if self.move_related(node) {
+                self.state[node] = NodeState::Freeze;
+                self.freeze_worklist.insert(node);
+            } else {
+                self.state[node] = NodeState::Simplify;
+                self.simplify_worklist.insert(node);
+            }
+        }
+    }
+
+    // 尚未从图中删除的邻接结点
+    fn adjacent(&self, node: usize) -> Vec<usize> {
+        self.adj_list[node]
+            .iter()
+            .copied()
+            .filter(|&n| !matches!(self.state[n], NodeState::OnStack | NodeState::Coalesced))
+            .collect()
+    }
+
+    // 还可能被合并的传送指令
+    fn node_moves(&self, node: usize) -> Vec<usize> {
+        self.move_list[node]
+            .iter()
+            .copied()
+            .filter(|&mv| matches!(self.move_state[mv], MoveState::Active | MoveState::Worklist))
+            .collect()
+    }
+
+    fn move_related(&self, node: usize) -> bool {
+        !self.node_moves(node).is_empty()
+    }
+
+    fn set_state(&mut self, node: usize, state: NodeState) {
+        match self.state[node] {
+            NodeState::Simplify => self.simplify_worklist.remove(&node),
+            NodeState::Freeze => self.freeze_worklist.remove(&node),
+            NodeState::Spill => self.spill_worklist.remove(&node),
+            _ => false,
+        };
+        match state {
+            NodeState::Simplify => self.simplify_worklist.insert(node),
+            NodeState::Freeze => self.freeze_worklist.insert(node),
+            NodeState::Spill => self.spill_worklist.insert(node),
+            _ => false,
+        };
+        self.state[node] = state;
+    }
+
+    fn simplify(&mut self, node: usize) {
+        self.set_state(node, NodeState::OnStack);
+        self.select_stack.push(node);
+        for m in self.adjacent(node) {
+            self.decrement_degree(m);
+        }
+    }
+
+    fn decrement_degree(&mut self, node: usize) {
+        if self.is_precolored(node) {
+            return;
+        }
+        let degree = self.degree[node];
+        self.degree[node] -= 1;
+        if degree == K {
+            let mut nodes = self.adjacent(node);
+            nodes.push(node);
+            self.enable_moves(&nodes);
+            if self.move_related(node) {
+                self.set_state(node, NodeState::Freeze);
+            } else {
+                self.set_state(node, NodeState::Simplify);
+            }
+        }
+    }
+
+    fn enable_moves(&mut self, nodes: &[usize]) {
+        for &node in nodes {
+            for mv in self.node_moves(node) {
+                if self.move_state[mv] == MoveState::Active {
+                    self.move_state[mv] = MoveState::Worklist;
+                    self.worklist_moves.insert(mv);
+                }
+            }
+        }
+    }
+
+    fn alias(&self, mut node: usize) -> usize {
+        while self.state[node] == NodeState::Coalesced {
+            node = self.alias[node];
+        }
+        node
+    }
+
+    fn coalesce(&mut self, mv: usize) {
+        self.worklist_moves.remove(&mv);
+        let (x, y) = self.moves[mv];
+        let (x, y) = (self.alias(x), self.alias(y));
+        let (u, v) = if self.is_precolored(y) {
+            (y, x)
+        } else {
+            (x, y)
+        };
+        if u == v {
+            self.move_state[mv] = MoveState::Coalesced;
+            self.add_worklist(u);
+        } else if self.is_precolored(v) || self.adj_set.contains(&(u, v)) {
+            self.move_state[mv] = MoveState::Constrained;
+            self.add_worklist(u);
+            self.add_worklist(v);
+        } else if (self.is_precolored(u) && self.adjacent(v).iter().all(|&t| self.ok(t, u)))
+            || (!self.is_precolored(u) && self.conservative(u, v))
+        {
+            self.move_state[mv] = MoveState::Coalesced;
+            self.combine(u, v);
+            self.add_worklist(u);
+        } else {
+            self.move_state[mv] = MoveState::Active;
+        }
+    }
+
+    fn add_worklist(&mut self, node: usize) {
+        if !self.is_precolored(node) && !self.move_related(node) && self.degree[node] < K {
+            self.set_state(node, NodeState::Simplify);
+        }
+    }
+
+    // George 准则: t 与 r 合并后不会使 t 的邻接结点更难着色
+    fn ok(&self, t: usize, r: usize) -> bool {
+        self.degree[t] < K || self.is_precolored(t) || self.adj_set.contains(&(t, r))
+    }
+
+    // Briggs 准则: 合并后的结点的高度数邻接结点少于 K 个
+    fn conservative(&self, u: usize, v: usize) -> bool {
+        let mut nodes = self.adjacent(u);
+        nodes.extend(self.adjacent(v));
+        nodes.sort_unstable();
+        nodes.dedup();
+        nodes.iter().filter(|&&n| self.degree[n] >= K).count() < K
+    }
+
+    fn combine(&mut self, u: usize, v: usize) {
+        self.set_state(v, NodeState::Coalesced);
+        self.alias[v] = u;
+        let moves = self.move_list[v].clone();
+        self.move_list[u].extend(moves);
+        self.enable_moves(&[v]);
+        for t in self.adjacent(v) {
+            self.add_edge(t, u);
+            self.decrement_degree(t);
+        }
+        if self.degree[u] >= K && self.state[u] == NodeState::Freeze {
+            self.set_state(u, NodeState::Spill);
+        }
+    }
+
+    fn freeze(&mut self, node: usize) {
+        self.set_state(node, NodeState::Simplify);
+        self.freeze_moves(node);
+    }
+
+    // 放弃与结点相关的传送指令的合并
+    fn freeze_moves(&mut self, u: usize) {
+        for mv in self.node_moves(u) {
+            let (x, y) = self.moves[mv];
+            let v = if self.alias(y) == self.alias(u) {
+                self.alias(x)
+            } else {
+                self.alias(y)
+            };
+            self.move_state[mv] = MoveState::Frozen;
+            if self.state[v] == NodeState::Freeze && !self.move_related(v) && self.degree[v] < K {
+                self.set_state(v, NodeState::Simplify);
+            }
+        }
+    }
+
+    // 选择溢出代价与度数之比最小的结点, 循环中的值尽量留在寄存器中
+    fn select_spill(&mut self) {
+        let node = *self
+            .spill_worklist
+            .iter()
+            .min_by(|&&a, &&b| {
+                let a = self.cost[a] / self.degree[a] as f64;
+                let b = self.cost[b] / self.degree[b] as f64;
+                a.total_cmp(&b)
+            })
+            .unwrap();
+        self.set_state(node, NodeState::Simplify);
+        self.freeze_moves(node);
+    }
+
+    fn assign_colors(&mut self) {
+        while let Some(node) = self.select_stack.pop() {
+            // 按 REGS 的顺序优先使用 caller-saved 寄存器, 不需要在函数入口处保存
+            let mut ok_colors: BTreeSet<usize> = (0..K).collect();
+            for &w in &self.adj_list[node] {
+                let w = self.alias(w);
+                if matches!(self.state[w], NodeState::Colored | NodeState::Precolored) {
+                    ok_colors.remove(&self.color[w].unwrap());
+                }
+            }
+            match ok_colors.first() {
+                Some(&color) => {
+                    self.state[node] = NodeState::Colored;
+                    self.color[node] = Some(color);
+                }
+                None => self.state[node] = NodeState::Spilled,
+            }
+        }
+        for node in K..self.state.len() {
+            if self.state[node] == NodeState::Coalesced {
+                self.color[node] = self.color[self.alias(node)];
+            }
+        }
+    }
+}
+
+// 基本块所在循环的嵌套深度
+//
+// 按深度优先的逆后序, 指向不晚于自身的基本块的边是回边 (SysY 的控制流图总是可归约的),
+// 回边 b -> h 对应的自然循环由 h 以及不经过 h 能到达 b 的基本块组成
+fn loop_depth(func: &FunctionData) -> HashMap<BasicBlock, u32> {
+    let mut depth: HashMap<BasicBlock, u32> =
+        func.layout().bbs().keys().map(|&bb| (bb, 0)).collect();
+    let entry = match func.layout().entry_bb() {
+        Some(entry) => entry,
+        None => return depth,
+    };
+
+    // 后序遍历, 用显式的栈避免递归过深
+    let mut order = Vec::new();
+    let mut visited = HashSet::from([entry]);
+    let mut stack = vec![(entry, successors(func, entry), 0)];
+    while let Some((bb, succs, i)) = stack.last_mut() {
+        if let Some(&succ) = succs.get(*i) {
+            *i += 1;
+            if visited.insert(succ) {
+                stack.push((succ, successors(func, succ), 0));
+            }
+        } else {
+            order.push(*bb);
+            stack.pop();
+        }
+    }
+    let rpo: HashMap<BasicBlock, usize> = order
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &bb)| (bb, i))
+        .collect();
+
+    let mut preds: HashMap<BasicBlock, Vec<BasicBlock>> = HashMap::new();
+    for &bb in &order {
+        for succ in successors(func, bb) {
+            preds.entry(succ).or_default().push(bb);
+        }
+    }
+    for &bb in &order {
+        for header in successors(func, bb) {
+            if rpo[&header] > rpo[&bb] {
+                continue;
+            }
+            let mut body = HashSet::from([header]);
+            let mut worklist = vec![bb];
+            while let Some(b) = worklist.pop() {
+                if body.insert(b) {
+                    worklist.extend(preds.get(&b).into_iter().flatten().copied());
+                }
+            }
+            for b in body {
+                *depth.get_mut(&b).unwrap() += 1;
+            }
+        }
+    }
+    depth
+}
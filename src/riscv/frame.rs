@@ -0,0 +1,170 @@
+// 函数的栈帧布局, 从 sp 开始向高地址依次为:
+//   调用其他函数时通过栈传递的参数 (第 9 个及之后的参数)
+//   alloc 分配的局部变量和数组
+//   溢出到栈上的值
+//   调用其他函数前保存的 caller-saved 寄存器
+//   函数入口处保存的 callee-saved 寄存器
+//   保存的 ra (只有调用了其他函数时才需要)
+// 栈帧大小按 16 字节对齐
+// 本函数的第 9 个及之后的参数位于调用者的栈帧中, 即 sp + 栈帧大小 之上
+use super::{is_callee_saved, Allocation, Error, ARG_REGS};
+use koopa::ir::{FunctionData, TypeKind, Value, ValueKind};
+use std::collections::HashMap;
+
+/// 12 位有符号立即数能表示的范围
+pub const IMM12: std::ops::Range<i32> = -2048..2048;
+
+#[derive(Debug)]
+pub struct Frame {
+    size: i32,
+    // alloc、溢出的值以及栈上的参数相对 sp 的偏移量
+    slots: HashMap<Value, i32>,
+    // 保存寄存器的位置
+    saved_regs: HashMap<&'static str, i32>,
+    // 需要在入口处保存的 callee-saved 寄存器
+    callee_saved: Vec<&'static str>,
+    // 保存 ra 的位置
+    ra: Option<i32>,
+}
+
+impl Frame {
+    /// 为函数中所有的 alloc、溢出的值以及需要保存的寄存器分配栈上的位置
+    ///
+    /// caller_saved 为调用其他函数前需要保存的 caller-saved 寄存器
+    /// 栈帧中的偏移量超出 i32 的范围 (如过大的局部数组) 时报错
+    pub fn new(
+        func: &FunctionData,
+        allocation: &Allocation,
+        caller_saved: &[&'static str],
+    ) -> Result<Self, Error> {
+        // offset 之后再分配 size 字节, 返回新的 offset
+        let grow = |offset: i32, size: usize| {
+            i32::try_from(size)
+                .ok()
+                .and_then(|size| offset.checked_add(size))
+                .ok_or_else(|| Error::FrameTooLarge(func.name()[1..].to_string()))
+        };
+        let mut slots = HashMap::new();
+        let mut has_call = false;
+        let mut max_stack_args = 0;
+        for (_, node) in func.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if let ValueKind::Call(call) = func.dfg().value(inst).kind() {
+                    has_call = true;
+                    max_stack_args = max_stack_args.max(call.args().len().saturating_sub(8));
+                }
+            }
+        }
+
+        let mut offset = 4 * max_stack_args as i32;
+        for (_, node) in func.layout().bbs() {
+            for &inst in node.insts().keys() {
+                let value_data = func.dfg().value(inst);
+                if let ValueKind::Alloc(_) = value_data.kind() {
+                    slots.insert(inst, offset);
+                    offset = match value_data.ty().kind() {
+                        TypeKind::Pointer(base) => grow(offset, base.size())?,
+                        _ => unreachable!(),
+                    };
+                }
+            }
+        }
+        for &value in &allocation.spilled {
+            slots.insert(value, offset);
+            offset = grow(offset, 4)?;
+        }
+
+        let mut callee_saved: Vec<&'static str> = allocation
+            .regs
+            .values()
+            .copied()
+            .filter(|reg| is_callee_saved(reg))
+            .collect();
+        callee_saved.sort_unstable();
+        callee_saved.dedup();
+        let mut saved_regs = HashMap::new();
+        for &reg in caller_saved.iter().chain(&callee_saved) {
+            saved_regs.insert(reg, offset);
+            offset = grow(offset, 4)?;
+        }
+
+        if has_call {
+            offset = grow(offset, 4)?;
+        }
+        let size = grow(offset, 15)? / 16 * 16;
+
+        // 通过栈传递的参数
+        for (i, &param) in func.params().iter().enumerate().skip(ARG_REGS.len()) {
+            slots.insert(param, grow(size, 4 * (i - ARG_REGS.len()))?);
+        }
+        Ok(Frame {
+            size,
+            slots,
+            saved_regs,
+            callee_saved,
+            ra: has_call.then_some(size - 4),
+        })
+    }
+
+    /// alloc、溢出的值或栈上的参数相对 sp 的偏移量
+    pub fn slot(&self, value: Value) -> Option<i32> {
+        self.slots.get(&value).copied()
+    }
+
+    /// 保存寄存器的位置相对 sp 的偏移量
+    pub fn saved_reg(&self, reg: &str) -> i32 {
+        self.saved_regs[reg]
+    }
+
+    /// 函数入口: 分配栈帧, 保存 ra 和用到的 callee-saved 寄存器
+    pub fn prologue(&self, result: &mut String) {
+        if self.size > 0 {
+            add_sp(result, "sp", -self.size);
+        }
+        if let Some(ra) = self.ra {
+            access_stack(result, "sw", "ra", ra);
+        }
+        for &reg in &self.callee_saved {
+            access_stack(result, "sw", reg, self.saved_regs[reg]);
+        }
+    }
+
+    /// 函数返回前: 恢复 callee-saved 寄存器和 ra, 释放栈帧
+    pub fn epilogue(&self, result: &mut String) {
+        for &reg in &self.callee_saved {
+            access_stack(result, "lw", reg, self.saved_regs[reg]);
+        }
+        if let Some(ra) = self.ra {
+            access_stack(result, "lw", "ra", ra);
+        }
+        if self.size > 0 {
+            add_sp(result, "sp", self.size);
+        }
+    }
+}
+
+/// 计算 rd = sp + offset, 偏移量超出立即数范围时先用 li 加载到 t2
+pub fn add_sp(result: &mut String, rd: &str, offset: i32) {
+    if IMM12.contains(&offset) {
+        result.push_str(&format!("  addi  {}, sp, {}\n", rd, offset));
+    } else {
+        result.push_str(&format!("  li    t2, {}\n", offset));
+        result.push_str(&format!("  add   {}, sp, t2\n", rd));
+    }
+}
+
+/// 访问 sp + offset 处的栈上数据 (lw/sw), 偏移量超出立即数范围时先用 t2 算出地址
+pub fn access_stack(result: &mut String, op: &str, reg: &str, offset: i32) {
+    access_mem(result, op, reg, "sp", offset);
+}
+
+/// 访问 base + offset 处的数据, 偏移量超出立即数范围时先用 t2 算出地址
+pub fn access_mem(result: &mut String, op: &str, reg: &str, base: &str, offset: i32) {
+    if IMM12.contains(&offset) {
+        result.push_str(&format!("  {:<5} {}, {}({})\n", op, reg, offset, base));
+    } else {
+        result.push_str(&format!("  li    t2, {}\n", offset));
+        result.push_str(&format!("  add   t2, {}, t2\n", base));
+        result.push_str(&format!("  {:<5} {}, 0(t2)\n", op, reg));
+    }
+}
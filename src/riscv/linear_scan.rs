@@ -0,0 +1,142 @@
+// 线性扫描寄存器分配 (Poletto & Sarkar)
+//
+// 按起点顺序扫描活跃区间, 区间结束后释放其寄存器;
+// 没有空闲的寄存器时, 在当前区间和所有活跃的区间中选择下一次使用最远的值溢出到栈上
+// 跨过函数调用的值优先使用 callee-saved 寄存器, 以减少调用前后保存和恢复寄存器的次数
+use super::liveness::Liveness;
+use super::{is_callee_saved, Allocation, REGS};
+use koopa::ir::Value;
+use std::collections::HashSet;
+
+pub fn allocate(liveness: &Liveness) -> Allocation {
+    let mut allocation = Allocation::default();
+    let mut free: Vec<&'static str> = REGS.iter().rev().copied().collect();
+    // 当前占用寄存器的区间: (值, 终点, 寄存器)
+    let mut active: Vec<(Value, usize, &'static str)> = Vec::new();
+    let across_calls: HashSet<Value> = liveness
+        .live_after_calls
+        .values()
+        .flatten()
+        .copied()
+        .collect();
+
+    for &(value, start, end) in &liveness.intervals {
+        // 终点不晚于当前起点的区间已经结束, 指令的结果可以和它最后使用的操作数共用寄存器
+        active.retain(|&(_, active_end, reg)| {
+            if active_end <= start {
+                free.push(reg);
+            }
+            active_end > start
+        });
+
+        let prefer_callee_saved = across_calls.contains(&value);
+        let preferred = free
+            .iter()
+            .rposition(|reg| is_callee_saved(reg) == prefer_callee_saved);
+        if let Some(i) = preferred.or(free.len().checked_sub(1)) {
+            let reg = free.remove(i);
+            allocation.regs.insert(value, reg);
+            active.push((value, end, reg));
+            continue;
+        }
+
+        // 选择下一次使用最远的活跃区间, 如果比当前的值还远, 就把它的寄存器让给当前的值
+        let (i, &(victim, _, reg)) = active
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, &(value, _, _))| liveness.next_use(value, start))
+            .unwrap();
+        if liveness.next_use(victim, start) > liveness.next_use(value, start) {
+            allocation.regs.remove(&victim);
+            allocation.spilled.push(victim);
+            allocation.regs.insert(value, reg);
+            active[i] = (value, end, reg);
+        } else {
+            allocation.spilled.push(value);
+        }
+    }
+    allocation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::front::Driver;
+
+    // 同时活跃的值比寄存器多: %v0 到 %v29 都活跃到求和时, 并且都跨过一次调用
+    fn program() -> koopa::ir::Program {
+        let mut koopa = String::from("decl @getint(): i32\n\nfun @f(%x: i32): i32 {\n%entry:\n");
+        for i in 0..30 {
+            koopa.push_str(&format!("  %v{} = add %x, {}\n", i, i));
+        }
+        koopa.push_str("  %c = call @getint()\n  %s0 = add %v0, %c\n");
+        for i in 1..30 {
+            koopa.push_str(&format!("  %s{} = add %s{}, %v{}\n", i, i - 1, i));
+        }
+        koopa.push_str("  ret %s29\n}\n");
+        Driver::from(koopa).generate_program().unwrap()
+    }
+
+    #[test]
+    fn spill_when_out_of_registers() {
+        let program = program();
+        let func = program
+            .funcs()
+            .values()
+            .find(|func| func.name() == "@f")
+            .unwrap();
+        let liveness = Liveness::new(func);
+        let allocation = allocate(&liveness);
+
+        // 每个值要么分配到寄存器, 要么溢出
+        assert!(!allocation.spilled.is_empty());
+        for &(value, _, _) in &liveness.intervals {
+            let spilled = allocation.spilled.contains(&value);
+            assert!(allocation.regs.contains_key(&value) != spilled);
+        }
+        // 重叠的区间不能使用同一个寄存器, 一个区间的终点可以是另一个区间的起点
+        for (i, &(a, a_start, a_end)) in liveness.intervals.iter().enumerate() {
+            for &(b, b_start, b_end) in &liveness.intervals[i + 1..] {
+                let overlap = a_start < b_end && b_start < a_end;
+                if let (true, Some(ra), Some(rb)) =
+                    (overlap, allocation.regs.get(&a), allocation.regs.get(&b))
+                {
+                    assert_ne!(ra, rb);
+                }
+            }
+        }
+    }
+
+    // callee-saved 寄存器足够时, 跨过调用的值都使用 callee-saved 寄存器
+    #[test]
+    fn prefer_callee_saved_across_calls() {
+        let program = Driver::from(
+            "
+            decl @getint(): i32
+
+            fun @f(%x: i32): i32 {
+            %entry:
+              %a = add %x, 1
+              %b = add %x, 2
+              %c = call @getint()
+              %s = add %a, %b
+              %t = add %s, %c
+              ret %t
+            }",
+        )
+        .generate_program()
+        .unwrap();
+        let func = program
+            .funcs()
+            .values()
+            .find(|func| func.name() == "@f")
+            .unwrap();
+        let liveness = Liveness::new(func);
+        let allocation = allocate(&liveness);
+        let across: Vec<&Value> = liveness.live_after_calls.values().flatten().collect();
+        assert_eq!(across.len(), 2);
+        for value in across {
+            assert!(is_callee_saved(allocation.regs[value]));
+        }
+    }
+}
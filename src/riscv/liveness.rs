@@ -0,0 +1,224 @@
+// 活跃变量分析
+//
+// 按基本块的 layout 顺序给指令编号 (从 1 开始, 0 表示函数入口处参数的定义),
+// 基本块参数在跳转到该基本块的指令处被赋值, 同时视为在基本块的开头定义,
+// 由各基本块的 use/def 迭代求出 live-in/live-out 集合,
+// 再得到每个值的活跃区间、所有使用的位置, 以及每条 call 之后仍然活跃的值
+use super::ARG_REGS;
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug)]
+pub struct Liveness {
+    /// 每个基本块出口处活跃的值
+    pub live_out: HashMap<BasicBlock, HashSet<Value>>,
+    /// 值的活跃区间 [start, end], 按 start 排序
+    pub intervals: Vec<(Value, usize, usize)>,
+    /// 值被使用的位置, 按从小到大排序
+    pub uses: HashMap<Value, Vec<usize>>,
+    /// 每条 call 之后仍然活跃的值 (不包括 call 的结果), 它们的值需要跨过函数调用保持不变
+    pub live_after_calls: HashMap<Value, HashSet<Value>>,
+}
+
+/// 需要分配寄存器的值: 有结果的指令, alloc 除外 (总是在栈上)
+/// 以及通过寄存器传递的参数和基本块参数
+pub fn needs_reg(func: &FunctionData, value: Value) -> bool {
+    if value.is_global() {
+        return false;
+    }
+    let value_data = func.dfg().value(value);
+    match value_data.kind() {
+        ValueKind::FuncArgRef(arg) => arg.index() < ARG_REGS.len(),
+        ValueKind::BlockArgRef(_) => true,
+        ValueKind::Alloc(_) => false,
+        _ => func.layout().parent_bb(value).is_some() && !value_data.ty().is_unit(),
+    }
+}
+
+/// 需要分配寄存器的参数
+pub fn reg_params(func: &FunctionData) -> Vec<Value> {
+    func.params()
+        .iter()
+        .copied()
+        .filter(|&param| needs_reg(func, param))
+        .collect()
+}
+
+/// 基本块的后继
+pub fn successors(func: &FunctionData, bb: BasicBlock) -> Vec<BasicBlock> {
+    let last = match func.layout().bbs().node(&bb).unwrap().insts().back_key() {
+        Some(&last) => last,
+        None => return Vec::new(),
+    };
+    match func.dfg().value(last).kind() {
+        ValueKind::Branch(branch) => vec![branch.true_bb(), branch.false_bb()],
+        ValueKind::Jump(jump) => vec![jump.target()],
+        _ => Vec::new(),
+    }
+}
+
+/// 跳转指令传给各个目标基本块的实参: (基本块的参数, 实参)
+pub fn block_args(func: &FunctionData, inst: Value) -> Vec<(Value, Value)> {
+    let edges: Vec<(BasicBlock, &[Value])> = match func.dfg().value(inst).kind() {
+        ValueKind::Branch(branch) => vec![
+            (branch.true_bb(), branch.true_args()),
+            (branch.false_bb(), branch.false_args()),
+        ],
+        ValueKind::Jump(jump) => vec![(jump.target(), jump.args())],
+        _ => Vec::new(),
+    };
+    edges
+        .into_iter()
+        .flat_map(|(bb, args)| {
+            let params = func.dfg().bb(bb).params();
+            params.iter().copied().zip(args.iter().copied())
+        })
+        .collect()
+}
+
+/// 指令用到的需要分配寄存器的操作数
+pub fn operands(func: &FunctionData, inst: Value) -> Vec<Value> {
+    func.dfg()
+        .value(inst)
+        .kind()
+        .value_uses()
+        .filter(|&value| needs_reg(func, value))
+        .collect()
+}
+
+impl Liveness {
+    pub fn new(func: &FunctionData) -> Self {
+        // 所有需要分配寄存器的值, 按定义的顺序排列
+        let mut values = reg_params(func);
+        let mut pos = 0;
+        let mut bb_range = HashMap::new();
+        let mut uses: HashMap<Value, Vec<usize>> = HashMap::new();
+        let mut use_sets = HashMap::new();
+        let mut defs = Vec::new();
+        let mut def_sets = HashMap::new();
+        for (&bb, node) in func.layout().bbs() {
+            let start = pos + 1;
+            let mut use_set = HashSet::new();
+            let mut def_set: HashSet<Value> = func.dfg().bb(bb).params().iter().copied().collect();
+            values.extend(func.dfg().bb(bb).params());
+            for &inst in node.insts().keys() {
+                pos += 1;
+                for value in operands(func, inst) {
+                    uses.entry(value).or_default().push(pos);
+                    if !def_set.contains(&value) {
+                        use_set.insert(value);
+                    }
+                }
+                // 跳转时给目标基本块的参数赋值
+                for (param, _) in block_args(func, inst) {
+                    defs.push((param, pos));
+                }
+                def_set.insert(inst);
+                if needs_reg(func, inst) {
+                    values.push(inst);
+                }
+            }
+            bb_range.insert(bb, (start, pos.max(start)));
+            use_sets.insert(bb, use_set);
+            def_sets.insert(bb, def_set);
+        }
+
+        // live_in = use ∪ (live_out - def), live_out 为所有后继的 live_in 之并, 迭代到不动点
+        let bbs: Vec<BasicBlock> = func.layout().bbs().keys().copied().collect();
+        let mut live_in: HashMap<BasicBlock, HashSet<Value>> = HashMap::new();
+        let mut live_out: HashMap<BasicBlock, HashSet<Value>> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &bb in bbs.iter().rev() {
+                let out: HashSet<Value> = successors(func, bb)
+                    .iter()
+                    .flat_map(|succ| live_in.get(succ).into_iter().flatten().copied())
+                    .collect();
+                let mut in_: HashSet<Value> = use_sets[&bb].clone();
+                in_.extend(out.difference(&def_sets[&bb]));
+                if live_in.get(&bb) != Some(&in_) {
+                    live_in.insert(bb, in_);
+                    changed = true;
+                }
+                live_out.insert(bb, out);
+            }
+        }
+
+        // 逆序扫描每个基本块, 得到 call 之后活跃的值
+        let mut live_after_calls = HashMap::new();
+        for (&bb, node) in func.layout().bbs() {
+            let mut live = live_out[&bb].clone();
+            let insts: Vec<Value> = node.insts().keys().copied().collect();
+            for &inst in insts.iter().rev() {
+                live.remove(&inst);
+                if let ValueKind::Call(_) = func.dfg().value(inst).kind() {
+                    live_after_calls.insert(inst, live.clone());
+                }
+                live.extend(operands(func, inst));
+            }
+        }
+
+        // 区间从定义处开始, 覆盖所有的使用, 以及值活跃的基本块
+        let mut ranges: HashMap<Value, (usize, usize)> = HashMap::new();
+        let mut extend = |value: Value, at: usize| {
+            let range = ranges.entry(value).or_insert((at, at));
+            range.0 = range.0.min(at);
+            range.1 = range.1.max(at);
+        };
+        let mut at = 0;
+        for (_, node) in func.layout().bbs() {
+            for &inst in node.insts().keys() {
+                at += 1;
+                if needs_reg(func, inst) {
+                    extend(inst, at);
+                }
+            }
+        }
+        for param in reg_params(func) {
+            extend(param, 0);
+        }
+        for &(param, at) in &defs {
+            extend(param, at);
+        }
+        for &bb in &bbs {
+            for &param in func.dfg().bb(bb).params() {
+                extend(param, bb_range[&bb].0);
+            }
+        }
+        for (&value, at) in &uses {
+            for &at in at {
+                extend(value, at);
+            }
+        }
+        for &bb in &bbs {
+            let (start, end) = bb_range[&bb];
+            for &value in &live_in[&bb] {
+                extend(value, start);
+            }
+            for &value in &live_out[&bb] {
+                extend(value, end);
+            }
+        }
+        let mut intervals: Vec<(Value, usize, usize)> = values
+            .into_iter()
+            .map(|value| (value, ranges[&value].0, ranges[&value].1))
+            .collect();
+        intervals.sort_by_key(|&(_, start, end)| (start, end));
+
+        Liveness {
+            live_out,
+            intervals,
+            uses,
+            live_after_calls,
+        }
+    }
+
+    /// 值在 at 之后 (不含) 下一次被使用的位置, 不再使用时为 usize::MAX
+    pub fn next_use(&self, value: Value, at: usize) -> usize {
+        self.uses
+            .get(&value)
+            .and_then(|uses| uses.iter().find(|&&pos| pos > at).copied())
+            .unwrap_or(usize::MAX)
+    }
+}
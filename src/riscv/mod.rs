@@ -0,0 +1,791 @@
+// RISC-V 后端: 由内存形式的 Koopa IR 生成 RV32IM 汇编
+//
+// 寄存器的使用:
+//   t0, t1 用于加载溢出到栈上的操作数和常量, 暂存溢出的值的计算结果, 以及并行传送时打破环和栈到栈的传送
+//   t2 用于计算超出立即数范围的栈上地址
+//   其余的寄存器分配给指令的结果和参数
+//
+// 调用约定 (RV32 ILP32):
+//   前 8 个参数通过 a0-a7 传递, 其余的参数依次放在调用者栈帧的底部, 返回值放在 a0 中
+//   t*、a* 是 caller-saved 寄存器, 调用前后保存和恢复其中仍然活跃的值
+//   s0-s11 是 callee-saved 寄存器, 用到的在函数入口处保存, 返回前恢复
+mod coloring;
+mod frame;
+mod linear_scan;
+mod liveness;
+
+use crate::diagnostic::Diagnostic;
+use frame::{access_mem, access_stack, add_sp, Frame, IMM12};
+use koopa::ir::{
+    BasicBlock, BinaryOp, Function, FunctionData, Program, Type, TypeKind, Value, ValueKind,
+};
+use liveness::{reg_params, Liveness};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+
+// 可以分配给值的寄存器, 按分配时的优先顺序排列
+const REGS: [&str; 24] = [
+    "t3", "t4", "t5", "t6", "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7", "s0", "s1", "s2", "s3",
+    "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11",
+];
+
+// 不超过这个字数的 zeroinit 逐字写入, 更大的用循环清零
+const ZERO_UNROLL_WORDS: i32 = 16;
+
+// 传递参数的寄存器
+const ARG_REGS: [&str; 8] = ["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"];
+
+fn is_callee_saved(reg: &str) -> bool {
+    reg.starts_with('s')
+}
+
+/// 寄存器分配的结果
+#[derive(Debug, Default)]
+struct Allocation {
+    // 分配到寄存器的值
+    regs: HashMap<Value, &'static str>,
+    // 溢出到栈上的值
+    spilled: Vec<Value>,
+}
+
+/// 生成整个程序的汇编
+///
+/// 优化级别 opt_level 不低于 2 时使用图着色寄存器分配, 否则使用更快的线性扫描
+pub fn generate_asm(program: &Program, opt_level: u32) -> Result<String, Error> {
+    // RV32 中指针占 4 字节
+    Type::set_ptr_size(4);
+    check_scalar(program)?;
+    let mut result = String::new();
+    let options = Options { opt_level };
+    let names = Names::new(program);
+    program.generate(&mut result, &options, &names)?;
+    Ok(result)
+}
+
+/// 无法生成汇编的 Koopa IR
+///
+/// 由 SysY 前端生成的 IR 只会出现过大的数组, 其他情况只可能来自 -koopa-in 读入的 IR
+#[derive(Debug)]
+pub enum Error {
+    // 参数、基本块参数或指令的结果是数组, 无法放在寄存器或一个栈槽中
+    NonScalarValue { func: String, ty: Type },
+    // 栈帧的大小超出了 i32 的范围
+    FrameTooLarge(String),
+    // 数组的大小超出了 i32 的范围, 无法计算元素的地址
+    TypeTooLarge { func: String, ty: Type },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NonScalarValue { func, ty } => write!(
+                f,
+                "unsupported value of type `{}` in function `{}`: only `i32` and pointers can be held in registers",
+                ty, func
+            ),
+            Error::FrameTooLarge(func) => write!(f, "stack frame of function `{}` is too large", func),
+            Error::TypeTooLarge { func, ty } => {
+                write!(f, "type `{}` in function `{}` is too large", ty, func)
+            }
+        }
+    }
+}
+
+impl From<Error> for Diagnostic {
+    fn from(err: Error) -> Self {
+        Diagnostic::error(err.to_string())
+    }
+}
+
+// 检查所有需要寄存器或栈槽的值都是 i32 或指针
+// 数组类型的常量 (aggregate/zeroinit/undef) 只会作为 store 的源, 逐个元素写入, 不受限制
+fn check_scalar(program: &Program) -> Result<(), Error> {
+    for func_data in program.funcs().values() {
+        let bb_params = func_data
+            .layout()
+            .bbs()
+            .keys()
+            .flat_map(|&bb| func_data.dfg().bb(bb).params());
+        let insts = func_data
+            .layout()
+            .bbs()
+            .iter()
+            .flat_map(|(_, node)| node.insts().keys());
+        for &value in func_data.params().iter().chain(bb_params).chain(insts) {
+            let ty = func_data.dfg().value(value).ty();
+            if !matches!(
+                ty.kind(),
+                TypeKind::Int32 | TypeKind::Pointer(_) | TypeKind::Unit
+            ) {
+                return Err(Error::NonScalarValue {
+                    func: func_data.name()[1..].to_string(),
+                    ty: ty.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+// 生成汇编时的选项
+struct Options {
+    opt_level: u32,
+}
+
+// 全局变量和函数在汇编中的标号
+struct Names {
+    globals: HashMap<Value, String>,
+    funcs: HashMap<Function, String>,
+}
+
+impl Names {
+    // 标号为去掉 @ 前缀的名字
+    fn new(program: &Program) -> Self {
+        let globals = program
+            .inst_layout()
+            .iter()
+            .map(|&value| {
+                let name = program.borrow_value(value).name().as_ref().unwrap()[1..].to_string();
+                (value, name)
+            })
+            .collect();
+        let funcs = program
+            .funcs()
+            .iter()
+            .map(|(&func, func_data)| (func, func_data.name()[1..].to_string()))
+            .collect();
+        Names { globals, funcs }
+    }
+}
+
+// 根据内存形式 Koopa IR 生成汇编
+trait GenerateAsm {
+    fn generate(&self, result: &mut String, options: &Options, names: &Names) -> Result<(), Error>;
+}
+
+impl GenerateAsm for Program {
+    fn generate(&self, result: &mut String, options: &Options, names: &Names) -> Result<(), Error> {
+        // 全局变量放在 .data 段
+        if !self.inst_layout().is_empty() {
+            result.push_str("  .data\n");
+        }
+        for &value in self.inst_layout() {
+            let value_data = self.borrow_value(value);
+            let name = &names.globals[&value];
+            result.push_str(&format!("  .global {}\n{}:\n", name, name));
+            match value_data.kind() {
+                ValueKind::GlobalAlloc(alloc) => global_init(self, alloc.init(), result),
+                _ => unreachable!(),
+            }
+            result.push('\n');
+        }
+
+        result.push_str("  .text\n");
+        for &func in self.func_layout() {
+            let func_data = self.func(func);
+            // 函数声明 (如 SysY 库函数) 没有函数体, 不需要生成代码
+            if func_data.layout().entry_bb().is_none() {
+                continue;
+            }
+            result.push_str(&format!("  .global {}\n", names.funcs[&func]));
+            func_data.generate(result, options, names)?;
+        }
+        Ok(())
+    }
+}
+
+// 全局变量的初始值
+fn global_init(program: &Program, init: Value, result: &mut String) {
+    let init_data = program.borrow_value(init);
+    match init_data.kind() {
+        ValueKind::Integer(int) => result.push_str(&format!("  .word {}\n", int.value())),
+        // undef 可以取任意值, 和 zeroinit 一样填充 0
+        ValueKind::ZeroInit(_) | ValueKind::Undef(_) => {
+            result.push_str(&format!("  .zero {}\n", init_data.ty().size()))
+        }
+        // 数组按元素的顺序依次输出
+        ValueKind::Aggregate(aggregate) => {
+            for &elem in aggregate.elems() {
+                global_init(program, elem, result);
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+impl GenerateAsm for FunctionData {
+    fn generate(&self, result: &mut String, options: &Options, names: &Names) -> Result<(), Error> {
+        result.push_str(&format!("{}:\n", &self.name()[1..]));
+
+        let liveness = Liveness::new(self);
+        let allocation = if options.opt_level >= 2 {
+            coloring::allocate(self, &liveness)
+        } else {
+            linear_scan::allocate(&liveness)
+        };
+        // 在某个 call 之后仍然活跃的值所在的 caller-saved 寄存器需要在调用前保存
+        let mut caller_saved: Vec<&'static str> = liveness
+            .live_after_calls
+            .values()
+            .flatten()
+            .filter_map(|value| allocation.regs.get(value).copied())
+            .filter(|reg| !is_callee_saved(reg))
+            .collect();
+        caller_saved.sort_unstable();
+        caller_saved.dedup();
+        let env = Env {
+            func: self,
+            names,
+            frame: Frame::new(self, &allocation, &caller_saved)?,
+            regs: allocation.regs,
+            liveness,
+            zero_loops: Cell::new(0),
+        };
+        env.frame.prologue(result);
+        env.receive_params(result);
+
+        let entry = self.layout().entry_bb();
+        for (&bb, node) in self.layout().bbs() {
+            // 入口基本块紧跟在函数名之后, 不需要单独的标号
+            if Some(bb) != entry {
+                result.push_str(&format!("{}:\n", bb_label(self, bb)));
+            }
+            for &inst in node.insts().keys() {
+                env.generate_inst(result, inst)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// 基本块在汇编中的标号, 形如 .Lmain.then_0
+// 以函数名区分不同函数中的基本块, 标识符中不会出现的 . 作为分隔符,
+// 这样标号不会与其他函数的基本块、全局变量或函数名重复
+fn bb_label(func: &FunctionData, bb: BasicBlock) -> String {
+    let name = func.dfg().bb(bb).name().as_ref().unwrap();
+    format!(".L{}.{}", &func.name()[1..], &name[1..])
+}
+
+// 值所在的位置
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Loc<'a> {
+    Reg(&'static str),
+    // 相对 sp 的偏移量
+    Stack(i32),
+    // 常量, 只能作为传送的源
+    Imm(i32),
+    // 全局变量的地址, 用 la 取得, 只能作为传送的源
+    Global(&'a str),
+}
+
+// 把各个源位置的值同时传送到对应的目标位置
+// 依次完成目标不再被读取的传送, 只剩下环时借助 t0 打破, 栈到栈的传送经过 t1
+fn parallel_move(result: &mut String, mut moves: Vec<(Loc<'_>, Loc<'_>)>) {
+    moves.retain(|(dst, src)| dst != src);
+    while !moves.is_empty() {
+        let ready = moves
+            .iter()
+            .position(|&(dst, _)| moves.iter().all(|&(_, src)| src != dst));
+        match ready {
+            Some(i) => {
+                let (dst, src) = moves.remove(i);
+                move_loc(result, dst, src);
+            }
+            None => {
+                let (_, src) = moves[0];
+                move_loc(result, Loc::Reg("t0"), src);
+                for (_, s) in moves.iter_mut().filter(|(_, s)| *s == src) {
+                    *s = Loc::Reg("t0");
+                }
+            }
+        }
+    }
+}
+
+fn move_loc(result: &mut String, dst: Loc<'_>, src: Loc<'_>) {
+    match (dst, src) {
+        (Loc::Reg(rd), Loc::Reg(rs)) => result.push_str(&format!("  mv    {}, {}\n", rd, rs)),
+        (Loc::Reg(rd), Loc::Stack(offset)) => access_stack(result, "lw", rd, offset),
+        (Loc::Reg(rd), Loc::Imm(imm)) => result.push_str(&format!("  li    {}, {}\n", rd, imm)),
+        (Loc::Reg(rd), Loc::Global(name)) => {
+            result.push_str(&format!("  la    {}, {}\n", rd, name))
+        }
+        (Loc::Stack(offset), Loc::Reg(rs)) => access_stack(result, "sw", rs, offset),
+        (Loc::Stack(_), _) => {
+            move_loc(result, Loc::Reg("t1"), src);
+            move_loc(result, dst, Loc::Reg("t1"));
+        }
+        (Loc::Imm(_) | Loc::Global(_), _) => unreachable!(),
+    }
+}
+
+// 生成一个函数时需要的信息
+struct Env<'a> {
+    func: &'a FunctionData,
+    names: &'a Names,
+    // 分配到寄存器的值, 其余的值都在栈帧中
+    regs: HashMap<Value, &'static str>,
+    frame: Frame,
+    liveness: Liveness,
+    // 已经生成的清零循环的个数, 用于生成不重复的标号
+    zero_loops: Cell<usize>,
+}
+
+impl<'a> Env<'a> {
+    // 操作数所在的位置, zeroinit 视为 0, undef 可以取任意值, 也视为 0
+    // 全局变量作为操作数时表示它的地址
+    fn loc(&self, value: Value) -> Loc<'a> {
+        if value.is_global() {
+            return Loc::Global(&self.names.globals[&value]);
+        }
+        match self.func.dfg().value(value).kind() {
+            ValueKind::Integer(int) => Loc::Imm(int.value()),
+            ValueKind::ZeroInit(_) | ValueKind::Undef(_) => Loc::Imm(0),
+            _ => match self.regs.get(&value) {
+                Some(reg) => Loc::Reg(reg),
+                None => Loc::Stack(self.frame.slot(value).unwrap()),
+            },
+        }
+    }
+
+    // 把操作数读到寄存器中, 返回所在的寄存器
+    // 常量用 li 加载 (0 直接使用 x0), 溢出的值从栈上加载, 都使用 scratch 寄存器
+    fn read(&self, result: &mut String, value: Value, scratch: &'static str) -> &'static str {
+        match self.loc(value) {
+            Loc::Reg(reg) => reg,
+            Loc::Imm(0) => "x0",
+            src => {
+                move_loc(result, Loc::Reg(scratch), src);
+                scratch
+            }
+        }
+    }
+
+    // 指令的结果应当计算到的寄存器, 溢出的值先计算到 scratch 中
+    fn dest(&self, value: Value, scratch: &'static str) -> &'static str {
+        self.regs.get(&value).copied().unwrap_or(scratch)
+    }
+
+    // 计算完成后把溢出的值存回栈上
+    fn write(&self, result: &mut String, value: Value, reg: &str) {
+        if !self.regs.contains_key(&value) {
+            access_stack(result, "sw", reg, self.frame.slot(value).unwrap());
+        }
+    }
+
+    // 函数入口处把 a0-a7 中的参数传送到分配给它们的位置
+    fn receive_params(&self, result: &mut String) {
+        let moves = reg_params(self.func)
+            .into_iter()
+            .enumerate()
+            .map(|(i, param)| (self.loc(param), Loc::Reg(ARG_REGS[i])))
+            .collect();
+        parallel_move(result, moves);
+    }
+
+    // 准备实参, 调用函数, 并取得返回值
+    fn call(&self, result: &mut String, inst: Value, callee: Function, args: &[Value]) {
+        // 保存之后仍然活跃的值所在的 caller-saved 寄存器
+        let mut saved: Vec<&'static str> = self.liveness.live_after_calls[&inst]
+            .iter()
+            .filter_map(|value| self.regs.get(value).copied())
+            .filter(|reg| !is_callee_saved(reg))
+            .collect();
+        saved.sort_unstable();
+        for &reg in &saved {
+            access_stack(result, "sw", reg, self.frame.saved_reg(reg));
+        }
+
+        // 先把第 9 个及之后的实参放到栈上, 此时 a0-a7 还没有被改写
+        for (i, &arg) in args.iter().enumerate().skip(ARG_REGS.len()) {
+            let reg = self.read(result, arg, "t0");
+            access_stack(result, "sw", reg, 4 * (i - ARG_REGS.len()) as i32);
+        }
+        // 其余的实参同时传送到 a0-a7
+        let moves = args
+            .iter()
+            .zip(&ARG_REGS)
+            .map(|(&arg, &arg_reg)| (Loc::Reg(arg_reg), self.loc(arg)))
+            .collect();
+        parallel_move(result, moves);
+
+        result.push_str(&format!("  call  {}\n", self.names.funcs[&callee]));
+        if !self.func.dfg().value(inst).ty().is_unit() {
+            let rd = self.dest(inst, "a0");
+            if rd != "a0" {
+                result.push_str(&format!("  mv    {}, a0\n", rd));
+            }
+            self.write(result, inst, rd);
+        }
+        for &reg in &saved {
+            access_stack(result, "lw", reg, self.frame.saved_reg(reg));
+        }
+    }
+
+    // 把实参传送到目标基本块的参数, 然后跳转
+    fn jump(&self, result: &mut String, target: BasicBlock, args: &[Value]) {
+        let params = self.func.dfg().bb(target).params();
+        let moves = params
+            .iter()
+            .zip(args)
+            .map(|(&param, &arg)| (self.loc(param), self.loc(arg)))
+            .collect();
+        parallel_move(result, moves);
+        result.push_str(&format!("  j     {}\n", bb_label(self.func, target)));
+    }
+
+    // 把常量写入 base + offset 处的内存, undef 不需要写入
+    fn store_const(
+        &self,
+        result: &mut String,
+        value: Value,
+        base: &str,
+        offset: i32,
+    ) -> Result<(), Error> {
+        let value_data = self.func.dfg().value(value);
+        match value_data.kind() {
+            ValueKind::Integer(_) => {
+                let reg = self.read(result, value, "t0");
+                access_mem(result, "sw", reg, base, offset);
+            }
+            ValueKind::ZeroInit(_) => {
+                let words = self.size_of(value_data.ty())? / 4;
+                if words <= ZERO_UNROLL_WORDS {
+                    for i in 0..words {
+                        access_mem(result, "sw", "x0", base, offset + 4 * i);
+                    }
+                } else {
+                    self.zero_loop(result, base, offset, words);
+                }
+            }
+            ValueKind::Aggregate(aggregate) => {
+                // 整个数组的大小不超出 i32 的范围, 其中每个元素的偏移量也不会超出
+                self.size_of(value_data.ty())?;
+                for (i, &elem) in aggregate.elems().iter().enumerate() {
+                    let size = self.size_of(self.func.dfg().value(elem).ty())?;
+                    self.store_const(result, elem, base, offset + size * i as i32)?;
+                }
+            }
+            ValueKind::Undef(_) => {}
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    // 类型的大小, 超出 i32 的范围时报错
+    fn size_of(&self, ty: &Type) -> Result<i32, Error> {
+        i32::try_from(ty.size()).map_err(|_| Error::TypeTooLarge {
+            func: self.func.name()[1..].to_string(),
+            ty: ty.clone(),
+        })
+    }
+
+    // 用循环把 base + offset 开始的 words 个字清零, t0 为当前地址, t2 为结束地址
+    // 标号形如 .Lmain.zero.0, 比基本块的标号多一个 ., 不会重复
+    fn zero_loop(&self, result: &mut String, base: &str, offset: i32, words: i32) {
+        let n = self.zero_loops.get();
+        self.zero_loops.set(n + 1);
+        let label = format!(".L{}.zero.{}", &self.func.name()[1..], n);
+        if IMM12.contains(&offset) {
+            result.push_str(&format!("  addi  t0, {}, {}\n", base, offset));
+        } else {
+            result.push_str(&format!("  li    t0, {}\n", offset));
+            result.push_str(&format!("  add   t0, {}, t0\n", base));
+        }
+        result.push_str(&format!("  li    t2, {}\n", 4 * words));
+        result.push_str("  add   t2, t0, t2\n");
+        result.push_str(&format!("{}:\n", label));
+        result.push_str("  sw    x0, 0(t0)\n");
+        result.push_str("  addi  t0, t0, 4\n");
+        result.push_str(&format!("  bne   t0, t2, {}\n", label));
+    }
+
+    // 值是否为 i32 或指针, 只有这样的值能放在寄存器中
+    // 全局变量作为操作数时是指针
+    fn is_scalar(&self, value: Value) -> bool {
+        value.is_global()
+            || matches!(
+                self.func.dfg().value(value).ty().kind(),
+                TypeKind::Int32 | TypeKind::Pointer(_)
+            )
+    }
+
+    fn is_alloc(&self, value: Value) -> bool {
+        !value.is_global() && matches!(self.func.dfg().value(value).kind(), ValueKind::Alloc(_))
+    }
+
+    fn generate_inst(&self, result: &mut String, inst: Value) -> Result<(), Error> {
+        let value_data = self.func.dfg().value(inst);
+        match value_data.kind() {
+            // alloc 的位置已经在栈帧中分配好
+            ValueKind::Alloc(_) => {}
+            ValueKind::Load(load) => {
+                let rd = self.dest(inst, "t0");
+                let src = load.src();
+                if src.is_global() {
+                    // 先用 la 取得全局变量的地址
+                    result.push_str(&format!("  la    {}, {}\n", rd, self.names.globals[&src]));
+                    result.push_str(&format!("  lw    {}, 0({})\n", rd, rd));
+                } else if self.is_alloc(src) {
+                    access_stack(result, "lw", rd, self.frame.slot(src).unwrap());
+                } else {
+                    // 通过 getelemptr/getptr 计算出的指针访问
+                    let addr = self.read(result, src, "t0");
+                    result.push_str(&format!("  lw    {}, 0({})\n", rd, addr));
+                }
+                self.write(result, inst, rd);
+            }
+            ValueKind::Store(store) if !self.is_scalar(store.value()) => {
+                // 数组类型的常量 (aggregate/zeroinit/undef) 逐个元素写入内存
+                let dest = store.dest();
+                let (base, offset) = if dest.is_global() {
+                    result.push_str(&format!("  la    t1, {}\n", self.names.globals[&dest]));
+                    ("t1", 0)
+                } else if self.is_alloc(dest) {
+                    ("sp", self.frame.slot(dest).unwrap())
+                } else {
+                    (self.read(result, dest, "t1"), 0)
+                };
+                self.store_const(result, store.value(), base, offset)?;
+            }
+            ValueKind::Store(store) => {
+                let value = self.read(result, store.value(), "t0");
+                let dest = store.dest();
+                if dest.is_global() {
+                    result.push_str(&format!("  la    t1, {}\n", self.names.globals[&dest]));
+                    result.push_str(&format!("  sw    {}, 0(t1)\n", value));
+                } else if self.is_alloc(dest) {
+                    access_stack(result, "sw", value, self.frame.slot(dest).unwrap());
+                } else {
+                    let addr = self.read(result, dest, "t1");
+                    result.push_str(&format!("  sw    {}, 0({})\n", value, addr));
+                }
+            }
+            ValueKind::GetElemPtr(_) | ValueKind::GetPtr(_) => {
+                let (src, index) = match value_data.kind() {
+                    ValueKind::GetElemPtr(gep) => (gep.src(), gep.index()),
+                    ValueKind::GetPtr(gp) => (gp.src(), gp.index()),
+                    _ => unreachable!(),
+                };
+                let rd = self.dest(inst, "t0");
+                // 基地址: 全局数组用 la, 局部数组为 sp 加上偏移, 其他情况是指针的值
+                // 结果的寄存器可能与下标相同, 所以基地址总是放在 t0 中
+                let base = if src.is_global() {
+                    result.push_str(&format!("  la    t0, {}\n", self.names.globals[&src]));
+                    "t0"
+                } else if self.is_alloc(src) {
+                    add_sp(result, "t0", self.frame.slot(src).unwrap());
+                    "t0"
+                } else {
+                    self.read(result, src, "t0")
+                };
+                // 偏移量为下标乘以结果指针所指向的类型的大小, 下标为常量时直接算出偏移量
+                let size = match value_data.ty().kind() {
+                    TypeKind::Pointer(base) => self.size_of(base)?,
+                    _ => unreachable!(),
+                };
+                // 偏移量溢出 i32 时不能用 addi, 由 mul 计算 (结果按 32 位回绕, 与地址运算一致)
+                let offset = match self.func.dfg().value(index).kind() {
+                    ValueKind::Integer(int) => int.value().checked_mul(size),
+                    _ => None,
+                };
+                match offset {
+                    Some(offset) if IMM12.contains(&offset) => {
+                        result.push_str(&format!("  addi  {}, {}, {}\n", rd, base, offset));
+                    }
+                    _ => {
+                        let index = self.read(result, index, "t1");
+                        result.push_str(&format!("  li    t2, {}\n", size));
+                        result.push_str(&format!("  mul   t2, {}, t2\n", index));
+                        result.push_str(&format!("  add   {}, {}, t2\n", rd, base));
+                    }
+                }
+                self.write(result, inst, rd);
+            }
+            ValueKind::Binary(binary) => {
+                let lhs = self.read(result, binary.lhs(), "t0");
+                let rhs = self.read(result, binary.rhs(), "t1");
+                let rd = self.dest(inst, "t0");
+                let asm = match binary.op() {
+                    BinaryOp::Eq => format!("  xor   {rd}, {lhs}, {rhs}\n  seqz  {rd}, {rd}\n"),
+                    BinaryOp::NotEq => {
+                        format!("  xor   {rd}, {lhs}, {rhs}\n  snez  {rd}, {rd}\n")
+                    }
+                    BinaryOp::Gt => format!("  sgt   {rd}, {lhs}, {rhs}\n"),
+                    BinaryOp::Lt => format!("  slt   {rd}, {lhs}, {rhs}\n"),
+                    BinaryOp::Ge => format!("  slt   {rd}, {lhs}, {rhs}\n  seqz  {rd}, {rd}\n"),
+                    BinaryOp::Le => format!("  sgt   {rd}, {lhs}, {rhs}\n  seqz  {rd}, {rd}\n"),
+                    BinaryOp::Add => format!("  add   {rd}, {lhs}, {rhs}\n"),
+                    BinaryOp::Sub => format!("  sub   {rd}, {lhs}, {rhs}\n"),
+                    BinaryOp::Mul => format!("  mul   {rd}, {lhs}, {rhs}\n"),
+                    BinaryOp::Div => format!("  div   {rd}, {lhs}, {rhs}\n"),
+                    BinaryOp::Mod => format!("  rem   {rd}, {lhs}, {rhs}\n"),
+                    BinaryOp::And => format!("  and   {rd}, {lhs}, {rhs}\n"),
+                    BinaryOp::Or => format!("  or    {rd}, {lhs}, {rhs}\n"),
+                    BinaryOp::Xor => format!("  xor   {rd}, {lhs}, {rhs}\n"),
+                    BinaryOp::Shl => format!("  sll   {rd}, {lhs}, {rhs}\n"),
+                    BinaryOp::Shr => format!("  srl   {rd}, {lhs}, {rhs}\n"),
+                    BinaryOp::Sar => format!("  sra   {rd}, {lhs}, {rhs}\n"),
+                };
+                result.push_str(&asm);
+                self.write(result, inst, rd);
+            }
+            ValueKind::Call(call) => self.call(result, inst, call.callee(), call.args()),
+            ValueKind::Branch(branch)
+                if branch.true_args().is_empty() && branch.false_args().is_empty() =>
+            {
+                let cond = self.read(result, branch.cond(), "t0");
+                let true_bb = bb_label(self.func, branch.true_bb());
+                let false_bb = bb_label(self.func, branch.false_bb());
+                result.push_str(&format!("  bnez  {}, {}\n", cond, true_bb));
+                result.push_str(&format!("  j     {}\n", false_bb));
+            }
+            ValueKind::Branch(branch) => {
+                // 两个目标的参数不同, 各自在跳转前传送实参
+                let cond = self.read(result, branch.cond(), "t0");
+                let bb = self.func.layout().parent_bb(inst).unwrap();
+                // 基本块名中没有 ., 加上 .false 后缀也不会与其他标号重复
+                let false_edge = format!("{}.false", bb_label(self.func, bb));
+                result.push_str(&format!("  beqz  {}, {}\n", cond, false_edge));
+                self.jump(result, branch.true_bb(), branch.true_args());
+                result.push_str(&format!("{}:\n", false_edge));
+                self.jump(result, branch.false_bb(), branch.false_args());
+            }
+            ValueKind::Jump(jump) => self.jump(result, jump.target(), jump.args()),
+            ValueKind::Return(ret) => {
+                if let Some(value) = ret.value() {
+                    let reg = self.read(result, value, "a0");
+                    if reg != "a0" {
+                        result.push_str(&format!("  mv    a0, {}\n", reg));
+                    }
+                }
+                self.frame.epilogue(result);
+                result.push_str("  ret\n");
+            }
+            // 常量、参数和全局变量不会出现在基本块中
+            ValueKind::Integer(_)
+            | ValueKind::ZeroInit(_)
+            | ValueKind::Undef(_)
+            | ValueKind::Aggregate(_)
+            | ValueKind::FuncArgRef(_)
+            | ValueKind::BlockArgRef(_)
+            | ValueKind::GlobalAlloc(_) => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::front::Driver;
+
+    fn asm(koopa: &str) -> String {
+        let program = Driver::from(koopa).generate_program().unwrap();
+        generate_asm(&program, 0).unwrap()
+    }
+
+    // 依次执行 parallel_move 生成的指令, 返回寄存器和栈槽 (形如 4(sp)) 最终的值
+    fn run_moves(moves: Vec<(Loc<'_>, Loc<'_>)>, init: &[(&str, i32)]) -> HashMap<String, i32> {
+        let mut asm = String::new();
+        parallel_move(&mut asm, moves);
+        let mut state: HashMap<String, i32> = init
+            .iter()
+            .map(|&(loc, value)| (loc.to_string(), value))
+            .collect();
+        for line in asm.lines() {
+            let (op, operands) = line.trim().split_once(' ').unwrap();
+            let operands: Vec<&str> = operands.trim().split(", ").collect();
+            let (dst, value) = match op {
+                "mv" | "lw" => (operands[0], state[operands[1]]),
+                "sw" => (operands[1], state[operands[0]]),
+                "li" => (operands[0], operands[1].parse().unwrap()),
+                _ => panic!("unexpected instruction `{}`", line),
+            };
+            state.insert(dst.to_string(), value);
+        }
+        state
+    }
+
+    #[test]
+    fn parallel_move_swap() {
+        let moves = vec![
+            (Loc::Reg("a0"), Loc::Reg("a1")),
+            (Loc::Reg("a1"), Loc::Reg("a0")),
+        ];
+        let state = run_moves(moves, &[("a0", 0), ("a1", 1)]);
+        assert_eq!((state["a0"], state["a1"]), (1, 0));
+
+        // 栈到栈的交换也经过 t0 打破环
+        let moves = vec![
+            (Loc::Stack(0), Loc::Stack(4)),
+            (Loc::Stack(4), Loc::Stack(0)),
+        ];
+        let state = run_moves(moves, &[("0(sp)", 0), ("4(sp)", 4)]);
+        assert_eq!((state["0(sp)"], state["4(sp)"]), (4, 0));
+    }
+
+    #[test]
+    fn parallel_move_rotation() {
+        // a0 <- a1 <- 0(sp) <- a0 构成环, a2 和 a3 在环外读取 a0 和 a1 原来的值
+        let moves = vec![
+            (Loc::Reg("a0"), Loc::Reg("a1")),
+            (Loc::Reg("a1"), Loc::Stack(0)),
+            (Loc::Stack(0), Loc::Reg("a0")),
+            (Loc::Reg("a2"), Loc::Reg("a0")),
+            (Loc::Reg("a3"), Loc::Reg("a1")),
+            (Loc::Reg("a4"), Loc::Imm(7)),
+        ];
+        let state = run_moves(moves, &[("a0", 10), ("a1", 11), ("0(sp)", 12)]);
+        assert_eq!(state["a0"], 11);
+        assert_eq!(state["a1"], 12);
+        assert_eq!(state["0(sp)"], 10);
+        assert_eq!(state["a2"], 10);
+        assert_eq!(state["a3"], 11);
+        assert_eq!(state["a4"], 7);
+    }
+
+    // 第 9 个及之后的实参由调用者写入 sp 开始的位置, 被调用者从 sp + 栈帧大小 开始读取
+    #[test]
+    fn stack_args() {
+        let asm = asm("
+            decl @getint(): i32
+
+            fun @f(%a0: i32, %a1: i32, %a2: i32, %a3: i32, %a4: i32,
+                   %a5: i32, %a6: i32, %a7: i32, %a8: i32, %a9: i32): i32 {
+            %entry:
+              %x = call @getint()
+              %s = add %a8, %a9
+              %t = add %s, %x
+              ret %t
+            }
+
+            fun @main(): i32 {
+            %entry:
+              %r = call @f(0, 1, 2, 3, 4, 5, 6, 7, 8, 9)
+              ret %r
+            }");
+        let (f, main) = asm.split_once("main:").unwrap();
+        assert!(
+            main.contains("  li    t0, 8\n  sw    t0, 0(sp)\n"),
+            "{}",
+            main
+        );
+        assert!(
+            main.contains("  li    t0, 9\n  sw    t0, 4(sp)\n"),
+            "{}",
+            main
+        );
+        assert!(main.contains("  li    a7, 7\n"), "{}", main);
+
+        let size: i32 = f
+            .lines()
+            .find_map(|line| line.strip_prefix("  addi  sp, sp, -"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(f.contains(&format!("lw    t0, {}(sp)", size)), "{}", f);
+        assert!(f.contains(&format!("lw    t1, {}(sp)", size + 4)), "{}", f);
+    }
+}
@@ -0,0 +1,138 @@
+// 源文件以及字节偏移量到行号、列号的转换
+//
+// 行号和列号都从 1 开始, 列号按字符而不是字节计算
+use crate::ast::Span;
+
+#[derive(Debug)]
+pub struct SourceMap {
+    name: String,
+    source: String,
+    // 每一行开头的偏移量
+    line_starts: Vec<usize>,
+}
+
+impl SourceMap {
+    /// name 为显示在诊断信息中的文件名
+    pub fn new(name: impl Into<String>, source: impl Into<String>) -> Self {
+        let source = source.into();
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        SourceMap {
+            name: name.into(),
+            source,
+            line_starts,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// 偏移量所在的行号和列号, 超出文件末尾的偏移量视为文件末尾
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = self.floor_char_boundary(offset);
+        // 最后一个开头不晚于 offset 的行
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let col = self.source[self.line_starts[line - 1]..offset]
+            .chars()
+            .count()
+            + 1;
+        (line, col)
+    }
+
+    /// 第 line 行的内容, 不含换行符
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.source.len(), |&next| next - 1);
+        self.source[start..end].trim_end_matches('\r')
+    }
+
+    /// span 的起点所在行中 span 覆盖的部分, 跨越多行时截断到行尾
+    pub fn first_line_of(&self, span: Span) -> &str {
+        let start = self.floor_char_boundary(span.start);
+        let (line, _) = self.line_col(start);
+        let line_end = self.line_starts[line - 1] + self.line(line).len();
+        let end = self.floor_char_boundary(span.end.clamp(start, line_end.max(start)));
+        &self.source[start..end]
+    }
+
+    fn floor_char_boundary(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_col() {
+        let map = SourceMap::new("test.c", "int a;\nint b;\n");
+        assert_eq!(map.line_col(0), (1, 1));
+        assert_eq!(map.line_col(4), (1, 5));
+        // 换行符属于它所在的行
+        assert_eq!(map.line_col(6), (1, 7));
+        assert_eq!(map.line_col(7), (2, 1));
+    }
+
+    #[test]
+    fn line_col_at_eof() {
+        // 以换行符结尾时文件末尾是空的最后一行
+        let map = SourceMap::new("test.c", "int a;\n");
+        assert_eq!(map.line_col(7), (2, 1));
+        assert_eq!(map.line(2), "");
+        // 超出文件末尾的偏移量视为文件末尾
+        assert_eq!(map.line_col(100), (2, 1));
+
+        let map = SourceMap::new("test.c", "int a");
+        assert_eq!(map.line_col(5), (1, 6));
+
+        let map = SourceMap::new("test.c", "");
+        assert_eq!(map.line_col(0), (1, 1));
+        assert_eq!(map.line(1), "");
+    }
+
+    #[test]
+    fn line_col_multibyte() {
+        // 列号按字符计算, "变量" 占 6 个字节
+        let map = SourceMap::new("test.c", "// 变量\nint 变量 = 1;");
+        assert_eq!(map.line_col(3), (1, 4));
+        assert_eq!(map.line_col(10), (2, 1));
+        assert_eq!(map.line_col(14), (2, 5));
+        assert_eq!(map.line_col(21), (2, 8));
+        // 字符中间的偏移量视为这个字符的开头
+        assert_eq!(map.line_col(16), (2, 5));
+    }
+
+    #[test]
+    fn line_without_crlf() {
+        let map = SourceMap::new("test.c", "int a;\r\nint b;\r\n");
+        assert_eq!(map.line(1), "int a;");
+        assert_eq!(map.line(2), "int b;");
+    }
+
+    #[test]
+    fn first_line_of() {
+        let map = SourceMap::new("test.c", "int 变量 = 1;\nint b;\n");
+        assert_eq!(map.first_line_of(Span::new(4, 10)), "变量");
+        // 跨越多行时截断到行尾
+        assert_eq!(map.first_line_of(Span::new(4, 20)), "变量 = 1;");
+        // 字符中间的偏移量视为这个字符的开头
+        assert_eq!(map.first_line_of(Span::new(5, 9)), "变");
+        // 文件末尾的空 span
+        assert_eq!(map.first_line_of(Span::new(23, 23)), "");
+        assert_eq!(map.first_line_of(Span::new(100, 200)), "");
+    }
+}
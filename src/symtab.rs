@@ -0,0 +1,127 @@
+// 符号表: 由作用域组成的栈, 内层作用域中的定义可以遮蔽外层的同名定义
+// 生成 IR 和语义检查使用同一个符号表, 只是其中保存的内容不同
+use crate::ast::FuncType;
+use crate::irgen::Error;
+use koopa::ir::{Function, Value};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// 标识符在符号表中对应的对象
+#[derive(Debug, Clone)]
+pub enum Symbol {
+    // 编译期求值后的常量
+    Const(i32),
+    // 变量对应的 alloc
+    Var(Value),
+    // 数组对应的 alloc
+    Array(Value),
+    // 常量数组对应的 alloc 和编译期求出的元素, 元素可以出现在常量表达式中
+    ConstArray(Value, Rc<ConstArray>),
+    Func(Function),
+}
+
+/// 常量数组各维的长度, 以及按行优先展开后的全部元素
+#[derive(Debug)]
+pub struct ConstArray {
+    pub lens: Vec<usize>,
+    pub values: Vec<i32>,
+}
+
+impl ConstArray {
+    /// 标量常量, 视为 0 维的数组
+    pub fn scalar(value: i32) -> Self {
+        ConstArray {
+            lens: Vec::new(),
+            values: vec![value],
+        }
+    }
+
+    /// 下标对应的元素, 下标个数必须等于数组的维数, 越界时为 None
+    pub fn get(&self, indices: &[i32]) -> Option<i32> {
+        if indices.len() != self.lens.len() {
+            return None;
+        }
+        let mut index = 0;
+        for (&i, &len) in indices.iter().zip(&self.lens) {
+            let i = usize::try_from(i).ok().filter(|&i| i < len)?;
+            index = index * len + i;
+        }
+        Some(self.values[index])
+    }
+}
+
+/// SysY 运行时库中的函数: 函数名、各参数是否为数组 (int[]) 以及返回类型
+///
+/// 用户代码无需声明即可调用, 生成 IR 和语义检查时都预先定义在全局作用域中
+pub const SYSY_LIB: [(&str, &[bool], FuncType); 8] = [
+    ("getint", &[], FuncType::Int),
+    ("getch", &[], FuncType::Int),
+    ("getarray", &[true], FuncType::Int),
+    ("putint", &[false], FuncType::Void),
+    ("putch", &[false], FuncType::Void),
+    ("putarray", &[false, true], FuncType::Void),
+    ("starttime", &[], FuncType::Void),
+    ("stoptime", &[], FuncType::Void),
+];
+
+#[derive(Debug)]
+pub struct SymbolTable<T = Symbol> {
+    scopes: Vec<HashMap<String, T>>,
+}
+
+impl<T> Default for SymbolTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> SymbolTable<T> {
+    /// 新建只包含全局作用域的符号表
+    pub fn new() -> Self {
+        SymbolTable {
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    /// 进入一个新的块作用域
+    pub fn enter_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// 离开当前块作用域, 其中的定义全部失效, 返回这些定义
+    pub fn exit_scope(&mut self) -> HashMap<String, T> {
+        assert!(self.scopes.len() > 1, "cannot exit the global scope");
+        self.scopes.pop().unwrap()
+    }
+
+    /// 在当前作用域中定义标识符, 同一作用域中重复定义时报错
+    pub fn insert(&mut self, ident: &str, symbol: T) -> Result<(), Error> {
+        let scope = self.scopes.last_mut().unwrap();
+        if scope.contains_key(ident) {
+            return Err(Error::Redeclared(ident.to_string()));
+        }
+        scope.insert(ident.to_string(), symbol);
+        Ok(())
+    }
+
+    /// 由内向外查找标识符
+    pub fn lookup(&self, ident: &str) -> Result<T, Error>
+    where
+        T: Clone,
+    {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(ident).cloned())
+            .ok_or_else(|| Error::UndeclaredIdent(ident.to_string()))
+    }
+
+    /// 由内向外查找标识符, 返回可以修改的定义
+    pub fn lookup_mut(&mut self, ident: &str) -> Result<&mut T, Error> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(ident))
+            .ok_or_else(|| Error::UndeclaredIdent(ident.to_string()))
+    }
+}
@@ -0,0 +1,87 @@
+// 警告的种类以及开启了哪些警告
+//
+// 命令行中用 -W<名字> 开启警告, -Wno-<名字> 关闭警告, -Werror 把警告当作错误,
+// -Werror=<名字> 开启一种警告并只把它当作错误
+// 所有警告默认开启
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Warning {
+    /// 定义后从未使用的局部变量或常量
+    UnusedVariable,
+    /// return、break、continue 之后的语句
+    UnreachableCode,
+    /// 非 void 函数执行到末尾而没有 return
+    MissingReturn,
+    /// 除数或模数为常量 0
+    DivisionByZero,
+}
+
+impl Warning {
+    pub const ALL: [Warning; 4] = [
+        Warning::UnusedVariable,
+        Warning::UnreachableCode,
+        Warning::MissingReturn,
+        Warning::DivisionByZero,
+    ];
+
+    /// 命令行选项中使用的名字
+    pub fn name(self) -> &'static str {
+        match self {
+            Warning::UnusedVariable => "unused-variable",
+            Warning::UnreachableCode => "unreachable-code",
+            Warning::MissingReturn => "missing-return",
+            Warning::DivisionByZero => "division-by-zero",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Warning> {
+        Warning::ALL
+            .into_iter()
+            .find(|warning| warning.name() == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Warnings {
+    enabled: HashSet<Warning>,
+    // 由 -Werror=<名字> 单独当作错误的警告
+    errors: HashSet<Warning>,
+    /// 是否把警告当作错误
+    pub werror: bool,
+}
+
+impl Default for Warnings {
+    fn default() -> Self {
+        Warnings {
+            enabled: Warning::ALL.into_iter().collect(),
+            errors: HashSet::new(),
+            werror: false,
+        }
+    }
+}
+
+impl Warnings {
+    pub fn enable(&mut self, warning: Warning) {
+        self.enabled.insert(warning);
+    }
+
+    pub fn disable(&mut self, warning: Warning) {
+        self.enabled.remove(&warning);
+    }
+
+    pub fn is_enabled(&self, warning: Warning) -> bool {
+        self.enabled.contains(&warning)
+    }
+
+    /// 开启警告并把它当作错误
+    pub fn enable_error(&mut self, warning: Warning) {
+        self.enable(warning);
+        self.errors.insert(warning);
+    }
+
+    /// 警告是否当作错误报告
+    pub fn is_error(&self, warning: Warning) -> bool {
+        self.werror || self.errors.contains(&warning)
+    }
+}